@@ -0,0 +1,90 @@
+// Compares the plain interpreter with the block-cached one. Runs a synthetic ROM by
+// default, or the ROM in GBEMU_BENCH_ROM:
+//
+//   cargo bench --no-default-features --bench interpreter
+//   GBEMU_BENCH_ROM=game.gb cargo bench --no-default-features --bench interpreter
+
+use gbemu::gameboy::GameBoy;
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+const FRAMES: usize = 600;
+const RUNS: usize = 3;
+
+fn main() {
+    let rom = match env::var_os("GBEMU_BENCH_ROM") {
+        Some(path) => fs::read(&path).expect("Could not read GBEMU_BENCH_ROM"),
+        None => synthetic_rom(),
+    };
+
+    let plain = best_of(&rom, false);
+    let cached = best_of(&rom, true);
+
+    report("interpreter", plain);
+    report("block cache", cached);
+    println!(
+        "speedup: {:.2}x",
+        plain.as_secs_f64() / cached.as_secs_f64()
+    );
+}
+
+fn best_of(rom: &[u8], cached: bool) -> Duration {
+    (0..RUNS).map(|_| run(rom.to_vec(), cached)).min().unwrap()
+}
+
+fn run(rom: Vec<u8>, cached: bool) -> Duration {
+    let mut gb = GameBoy::new(rom);
+    if cached {
+        gb.cpu_mut().enable_block_cache();
+    }
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        gb.step_frame();
+        gb.audio_samples();
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<12} {} frames in {:>8.2?} ({:.0} fps)",
+        name,
+        FRAMES,
+        elapsed,
+        FRAMES as f64 / elapsed.as_secs_f64()
+    );
+}
+
+// A busy loop that never halts: copies 256 bytes from ROM to WRAM and checksums
+// them, over and over, with the LCD on.
+fn synthetic_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // nop; jp $0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+
+    let program = [
+        0x21, 0x00, 0x10, // loop: ld hl,$1000
+        0x11, 0x00, 0xC0, //       ld de,$C000
+        0x06, 0x00, //             ld b,0
+        0x2A, //             copy: ld a,(hl+)
+        0x12, //                   ld (de),a
+        0x13, //                   inc de
+        0x05, //                   dec b
+        0x20, 0xFA, //             jr nz,copy
+        0x21, 0x00, 0xC0, //       ld hl,$C000
+        0xAF, //                   xor a
+        0x86, //              sum: add a,(hl)
+        0x2C, //                   inc l
+        0x20, 0xFC, //             jr nz,sum
+        0xC3, 0x50, 0x01, //       jp loop
+    ];
+    rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+
+    for (i, byte) in rom[0x1000..0x1100].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    rom
+}
@@ -6,13 +6,12 @@ pub mod square;
 pub mod wave;
 
 use crate::apu::noise::Noise;
-use crate::apu::queue::AudioQueue;
+use crate::apu::queue::{AudioQueue, BUFFER_SIZE};
 use crate::apu::square::SquareWave;
 use crate::apu::wave::WaveChannel;
 use crate::cpu::EmulationMode;
 
 const SAMPLE_RATE: usize = 95;
-const SEQUENCER_PERIOD: usize = 8192;
 
 const NR50: u16 = 0xFF24;
 const NR51: u16 = 0xFF25;
@@ -39,7 +38,6 @@ impl Default for AudioRegisters {
 }
 
 pub struct Apu {
-    clocks: usize,
     sample_clocks: usize,
     channel1: SquareWave,
     channel2: SquareWave,
@@ -59,7 +57,6 @@ pub struct Apu {
 impl Apu {
     pub fn new(mode: EmulationMode) -> Self {
         Apu {
-            clocks: 0,
             sample_clocks: 0,
             channel1: SquareWave::new(),
             channel2: SquareWave::new(),
@@ -77,15 +74,18 @@ impl Apu {
         }
     }
 
-    pub fn tick(&mut self, cycles: usize) {
-        for _ in 0..cycles {
-            self.clocks += 1;
-            self.sample_clocks += 1;
+    pub fn tick(&mut self, mut cycles: usize) {
+        while cycles > 0 {
+            // Run up to the next sample.
+            let step = cycles.min(SAMPLE_RATE - self.sample_clocks);
 
-            self.channel1.tick(1);
-            self.channel2.tick(1);
-            self.channel3.tick(1);
-            self.channel4.tick(1);
+            self.sample_clocks += step;
+            cycles -= step;
+
+            self.channel1.run(step);
+            self.channel2.run(step);
+            self.channel3.run(step);
+            self.channel4.run(step);
 
             if self.sample_clocks >= SAMPLE_RATE {
                 self.sample_clocks -= SAMPLE_RATE;
@@ -94,47 +94,47 @@ impl Apu {
                 self.samples.push(left, right);
                 self.i += 1;
             }
+        }
+    }
 
-            if self.clocks >= SEQUENCER_PERIOD {
-                self.clocks -= SEQUENCER_PERIOD;
-
-                match self.seq_ptr {
-                    0 => {
-                        self.channel1.length_tick();
-                        self.channel2.length_tick();
-                        self.channel3.length_tick();
-                        self.channel4.length_tick();
-                    }
-                    2 => {
-                        self.channel1.sweep_tick();
-                        self.channel1.length_tick();
-                        self.channel2.length_tick();
-                        self.channel3.length_tick();
-                        self.channel4.length_tick();
-                    }
-                    4 => {
-                        self.channel1.length_tick();
-                        self.channel2.length_tick();
-                        self.channel3.length_tick();
-                        self.channel4.length_tick();
-                    }
-                    6 => {
-                        self.channel1.sweep_tick();
-                        self.channel1.length_tick();
-                        self.channel2.length_tick();
-                        self.channel3.length_tick();
-                        self.channel4.length_tick();
-                    }
-                    7 => {
-                        self.channel1.volume_tick();
-                        self.channel2.volume_tick();
-                        self.channel4.volume_tick();
-                    }
-                    _ => (),
-                }
-                self.seq_ptr = (self.seq_ptr + 1) % 8;
+    /// Steps the frame sequencer. Clocked by the falling edge of DIV bit 4 (bit 5 in
+    /// double speed), i.e. bit 12/13 of the system counter.
+    pub fn sequencer_tick(&mut self) {
+        match self.seq_ptr {
+            0 => {
+                self.channel1.length_tick();
+                self.channel2.length_tick();
+                self.channel3.length_tick();
+                self.channel4.length_tick();
+            }
+            2 => {
+                self.channel1.sweep_tick();
+                self.channel1.length_tick();
+                self.channel2.length_tick();
+                self.channel3.length_tick();
+                self.channel4.length_tick();
+            }
+            4 => {
+                self.channel1.length_tick();
+                self.channel2.length_tick();
+                self.channel3.length_tick();
+                self.channel4.length_tick();
             }
+            6 => {
+                self.channel1.sweep_tick();
+                self.channel1.length_tick();
+                self.channel2.length_tick();
+                self.channel3.length_tick();
+                self.channel4.length_tick();
+            }
+            7 => {
+                self.channel1.volume_tick();
+                self.channel2.volume_tick();
+                self.channel4.volume_tick();
+            }
+            _ => (),
         }
+        self.seq_ptr = (self.seq_ptr + 1) % 8;
     }
 
     fn audio_out_left(&mut self) -> f32 {
@@ -240,6 +240,12 @@ impl Apu {
         self.master_on = false;
     }
 
+    /// Clocks until the buffer being filled is moved to the queue.
+    pub fn cycles_until_event(&self) -> usize {
+        let samples = BUFFER_SIZE.saturating_sub(self.samples.filled());
+        samples * SAMPLE_RATE + SAMPLE_RATE - self.sample_clocks
+    }
+
     pub fn get_next_buffer(&mut self) -> (Option<Vec<f32>>, Option<Vec<f32>>) {
         self.samples.dequeue()
     }
@@ -289,4 +295,45 @@ mod tests {
             test_registers_with(d);
         }
     }
+
+    fn playing_apu() -> Apu {
+        let mut apu = Apu::new(EmulationMode::Dmg);
+        apu.set_byte(NR52, 0x80);
+        apu.set_byte(NR51, 0xFF);
+        apu.set_byte(NR50, 0x77);
+        // Square with sweep and a short length, wave and noise.
+        apu.set_byte(0xFF10, 0x15);
+        apu.set_byte(0xFF11, 0xB0);
+        apu.set_byte(0xFF12, 0xF1);
+        apu.set_byte(0xFF14, 0xC6);
+        apu.set_byte(0xFF1A, 0x80);
+        apu.set_byte(0xFF1C, 0x20);
+        apu.set_byte(0xFF1E, 0x85);
+        apu.set_byte(0xFF21, 0xF2);
+        apu.set_byte(0xFF22, 0x31);
+        apu.set_byte(0xFF23, 0x80);
+        apu
+    }
+
+    #[test]
+    fn test_bulk_tick_matches_per_clock() {
+        let mut per_clock = playing_apu();
+        let mut bulk = playing_apu();
+
+        // The frame sequencer runs at 512 Hz.
+        for _ in 0..20 {
+            for _ in 0..8192 {
+                per_clock.tick(1);
+            }
+            per_clock.sequencer_tick();
+
+            bulk.tick(8192);
+            bulk.sequencer_tick();
+        }
+
+        assert_eq!(bulk.get_byte(NR52), per_clock.get_byte(NR52));
+        assert_eq!(bulk.samples.queue_left, per_clock.samples.queue_left);
+        assert_eq!(bulk.samples.queue_right, per_clock.samples.queue_right);
+        assert!(!bulk.samples.queue_left.is_empty());
+    }
 }
@@ -34,6 +34,11 @@ impl AudioQueue {
         }
     }
 
+    /// Samples in the buffer currently being filled.
+    pub fn filled(&self) -> usize {
+        self.current_left.len()
+    }
+
     pub fn dequeue(&mut self) -> (Option<Vec<f32>>, Option<Vec<f32>>) {
         (self.queue_left.pop_front(), self.queue_right.pop_front())
     }
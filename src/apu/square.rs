@@ -91,6 +91,15 @@ impl SquareWave {
         }
     }
 
+    // `tick` handles at most one period per call, this runs any number of clocks.
+    pub fn run(&mut self, mut cycles: usize) {
+        while cycles > 0 {
+            let step = cycles.min(self.counter.max(1));
+            self.tick(step);
+            cycles -= step;
+        }
+    }
+
     pub fn tick(&mut self, cycles: usize) {
         if self.counter <= cycles {
             let delta = cycles - self.counter;
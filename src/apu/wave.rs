@@ -53,6 +53,19 @@ impl WaveChannel {
         out / 15.0 * 2.0 - 1.0
     }
 
+    // `tick` handles at most one period per call, this runs any number of clocks.
+    pub fn run(&mut self, mut cycles: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        while cycles > 0 {
+            let step = cycles.min(self.counter.max(1));
+            self.tick(step);
+            cycles -= step;
+        }
+    }
+
     pub fn tick(&mut self, cycles: usize) {
         if !self.enabled {
             return;
@@ -0,0 +1,98 @@
+// Optional link cable to another runner over TCP: one side passes
+// `--link-listen ADDR`, the other `--link-connect ADDR`.
+
+use gbemu::gameboy::link::RemoteLinkedGameBoy;
+use gbemu::gameboy::{GameBoy, FRAME_CYCLES};
+use gbemu::serial::net::TcpTransport;
+use gbemu::serial::NoCable;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// How long to sleep while waiting for the other side to catch up.
+const WAIT: Duration = Duration::from_micros(100);
+
+pub enum Machine {
+    Local(GameBoy),
+    // Unplugged once the other side hangs up.
+    Linked {
+        linked: RemoteLinkedGameBoy<TcpTransport>,
+        plugged: bool,
+    },
+}
+
+impl Machine {
+    pub fn listen(gb: GameBoy, addr: &str) -> Result<Machine, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+        let (stream, _) = listener
+            .accept()
+            .map_err(|e| format!("Could not accept link: {}", e))?;
+
+        Machine::linked(gb, stream)
+    }
+
+    pub fn connect(gb: GameBoy, addr: &str) -> Result<Machine, String> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+
+        Machine::linked(gb, stream)
+    }
+
+    fn linked(gb: GameBoy, stream: TcpStream) -> Result<Machine, String> {
+        let transport = TcpTransport::new(stream).map_err(|e| format!("Link error: {}", e))?;
+        Ok(Machine::Linked {
+            linked: RemoteLinkedGameBoy::new(gb, transport),
+            plugged: true,
+        })
+    }
+
+    pub fn gb(&mut self) -> &mut GameBoy {
+        match self {
+            Machine::Local(gb) => gb,
+            Machine::Linked { linked, .. } => &mut linked.gb,
+        }
+    }
+
+    pub fn step_frame(&mut self) -> Result<(), String> {
+        let (linked, plugged) = match self {
+            Machine::Linked { linked, plugged } if *plugged => (linked, plugged),
+            _ => {
+                self.gb().step_frame();
+                return Ok(());
+            }
+        };
+
+        let end = linked.clock() + FRAME_CYCLES as u64;
+
+        while linked.clock() < end {
+            let step = linked.step();
+            // Frames are counted in clocks here, but a stale VBlank would cut the
+            // first `GameBoy::step_frame` short if the cable is unplugged.
+            linked.gb.cpu_mut().mmu.gpu.vblank_event = false;
+
+            match step {
+                Ok(true) => (),
+                Ok(false) => thread::sleep(WAIT),
+                // The other side quit (it may just have run its last frame), carry on
+                // with the cable unplugged.
+                Err(e) if hung_up(&e) => {
+                    linked.gb.connect_serial(Box::new(NoCable));
+                    *plugged = false;
+                    break;
+                }
+                Err(e) => return Err(format!("Link error: {}", e)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn hung_up(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+    )
+}
@@ -0,0 +1,419 @@
+// Headless runner for batch testing and CI. Loads a ROM, runs it for a number of
+// frames (or until a condition is met) without any window or audio device, and
+// writes the final screen, audio and serial output to files.
+//
+// Usage:
+//   gbemu-headless ROM [--frames N] [--input FILE] [--until-serial TEXT]
+//                      [--fail-serial TEXT] [--screenshot FILE.png] [--filter NAME]
+//                      [--audio FILE.wav] [--serial FILE]
+//                      [--link-listen ADDR | --link-connect ADDR]
+//                      [--printer PREFIX] [--colorize[=COMBO]]
+//
+// Exit status: 0 on success, 1 if a `--until-serial` condition was not met in
+// time or a `--fail-serial` text was seen, 2 on usage or I/O errors.
+//
+// Input scripts contain one event per line, `<frame> <press|release> <key>`, with
+// keys being one of right, left, up, down, a, b, select, start. Lines starting
+// with `#` are ignored.
+//
+// `--filter` upscales the screenshot, e.g. `scale2x`, `hq4x`, `xbrz3` or `lcd3`
+// (see `VideoFilter::from_name`).
+//
+// With `--link-listen`/`--link-connect` two runners are joined by a link cable over
+// TCP. Both then run in step with each other, frames take as long as the slower
+// of the two.
+//
+// `--printer` plugs in a Game Boy Printer instead and writes every print to
+// PREFIX-1.png, PREFIX-2.png and so on.
+//
+// `--colorize` colours a DMG game with the CGB boot ROM's palette for its title, or
+// with `--colorize=left+b` and so on the palette for a boot button combo.
+
+mod link;
+mod output;
+
+use link::Machine;
+
+use gbemu::filter::VideoFilter;
+use gbemu::gameboy::{ButtonCombo, GameBoy, Key, AUDIO_SAMPLE_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gbemu::serial::printer::Printer;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const DEFAULT_FRAMES: usize = 600;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+struct Options {
+    rom: PathBuf,
+    frames: usize,
+    input: Option<PathBuf>,
+    until_serial: Option<String>,
+    fail_serial: Option<String>,
+    screenshot: Option<PathBuf>,
+    filter: VideoFilter,
+    audio: Option<PathBuf>,
+    serial: Option<PathBuf>,
+    link_listen: Option<String>,
+    link_connect: Option<String>,
+    printer: Option<String>,
+    // `Some(None)` picks the palette from the title.
+    colorize: Option<Option<ButtonCombo>>,
+}
+
+struct InputEvent {
+    frame: usize,
+    pressed: bool,
+    key: Key,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    match run(&options) {
+        Ok(code) => process::exit(code),
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<i32, String> {
+    let rom = fs::read(&options.rom)
+        .map_err(|e| format!("Could not read {}: {}", options.rom.display(), e))?;
+
+    let inputs = match &options.input {
+        Some(path) => parse_inputs(
+            &fs::read_to_string(path)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?,
+        )?,
+        None => Vec::new(),
+    };
+
+    let mut gb = GameBoy::new(rom);
+    if let Some(combo) = options.colorize {
+        gb.colorize(combo);
+    }
+    let tray = options.printer.as_ref().map(|_| {
+        let (printer, tray) = Printer::new();
+        gb.connect_serial(Box::new(printer));
+        tray
+    });
+    let mut machine = match (&options.link_listen, &options.link_connect) {
+        (Some(addr), _) => Machine::listen(gb, addr)?,
+        (None, Some(addr)) => Machine::connect(gb, addr)?,
+        (None, None) => Machine::Local(gb),
+    };
+
+    let mut serial = Vec::new();
+    let mut left_audio = Vec::new();
+    let mut right_audio = Vec::new();
+    let mut next_input = 0;
+    let mut frame = 0;
+    let mut prints = 0;
+    let mut status = match options.until_serial {
+        Some(_) => EXIT_FAILURE,
+        None => EXIT_SUCCESS,
+    };
+
+    'frames: while frame < options.frames {
+        let gb = machine.gb();
+
+        while next_input < inputs.len() && inputs[next_input].frame <= frame {
+            let input = &inputs[next_input];
+            if input.pressed {
+                gb.key_down(input.key);
+            } else {
+                gb.key_up(input.key);
+            }
+            next_input += 1;
+        }
+
+        machine.step_frame()?;
+        frame += 1;
+
+        let gb = machine.gb();
+
+        let (left, right) = gb.audio_samples();
+        left_audio.extend(left);
+        right_audio.extend(right);
+
+        serial.extend(gb.serial_output());
+
+        if let (Some(prefix), Some(tray)) = (&options.printer, &tray) {
+            for print in tray.take() {
+                prints += 1;
+                let path = PathBuf::from(format!("{}-{}.png", prefix, prints));
+                output::write_png(&path, print.width, print.height, &print.pixels)
+                    .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            }
+        }
+
+        let text = String::from_utf8_lossy(&serial);
+
+        if let Some(fail) = &options.fail_serial {
+            if text.contains(fail.as_str()) {
+                status = EXIT_FAILURE;
+                break 'frames;
+            }
+        }
+
+        if let Some(until) = &options.until_serial {
+            if text.contains(until.as_str()) {
+                status = EXIT_SUCCESS;
+                break 'frames;
+            }
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        let mut screen = Vec::new();
+        let scale = options.filter.scale();
+        options.filter.apply(
+            machine.gb().framebuffer(),
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            &mut screen,
+        );
+
+        output::write_png(path, SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, &screen)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+
+    if let Some(path) = &options.audio {
+        output::write_wav(path, AUDIO_SAMPLE_RATE, &left_audio, &right_audio)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+
+    if let Some(path) = &options.serial {
+        fs::write(path, &serial)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+
+    println!("ran {} frames, exit status {}", frame, status);
+
+    Ok(status)
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        input: None,
+        until_serial: None,
+        fail_serial: None,
+        screenshot: None,
+        filter: VideoFilter::None,
+        audio: None,
+        serial: None,
+        link_listen: None,
+        link_connect: None,
+        printer: None,
+        colorize: None,
+    };
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--frames" => {
+                options.frames = value()?
+                    .parse()
+                    .map_err(|_| String::from("--frames expects a number"))?
+            }
+            "--input" => options.input = Some(PathBuf::from(value()?)),
+            "--until-serial" => options.until_serial = Some(value()?),
+            "--fail-serial" => options.fail_serial = Some(value()?),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+            "--filter" => {
+                let name = value()?;
+                options.filter = VideoFilter::from_name(&name)
+                    .ok_or_else(|| format!("Unknown filter {}", name))?
+            }
+            "--audio" => options.audio = Some(PathBuf::from(value()?)),
+            "--serial" => options.serial = Some(PathBuf::from(value()?)),
+            "--link-listen" => options.link_listen = Some(value()?),
+            "--link-connect" => options.link_connect = Some(value()?),
+            "--printer" => options.printer = Some(value()?),
+            "--colorize" => options.colorize = Some(None),
+            _ if arg.starts_with("--colorize=") => {
+                let name = &arg["--colorize=".len()..];
+                let combo = ButtonCombo::ALL
+                    .iter()
+                    .find(|combo| {
+                        let short = combo.name().split(':').next().unwrap();
+                        short.eq_ignore_ascii_case(name)
+                    })
+                    .ok_or_else(|| format!("Unknown button combo {}", name))?;
+                options.colorize = Some(Some(*combo));
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => rom = Some(PathBuf::from(arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| String::from("Usage: gbemu-headless ROM [options]"))?;
+
+    if options.link_listen.is_some() && options.link_connect.is_some() {
+        return Err(String::from(
+            "--link-listen and --link-connect can't be used together",
+        ));
+    }
+
+    if options.printer.is_some()
+        && (options.link_listen.is_some() || options.link_connect.is_some())
+    {
+        return Err(String::from("--printer can't be used with a link cable"));
+    }
+
+    Ok(options)
+}
+
+fn parse_inputs(script: &str) -> Result<Vec<InputEvent>, String> {
+    let mut inputs = Vec::new();
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let error = || format!("Invalid input on line {}: {}", i + 1, line);
+
+        if parts.len() != 3 {
+            return Err(error());
+        }
+
+        let frame = parts[0].parse().map_err(|_| error())?;
+
+        let pressed = match parts[1] {
+            "press" => true,
+            "release" => false,
+            _ => return Err(error()),
+        };
+
+        let key = match parts[2].to_lowercase().as_str() {
+            "right" => Key::Right,
+            "left" => Key::Left,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "a" => Key::BtnA,
+            "b" => Key::BtnB,
+            "select" => Key::Select,
+            "start" => Key::Start,
+            _ => return Err(error()),
+        };
+
+        inputs.push(InputEvent {
+            frame,
+            pressed,
+            key,
+        });
+    }
+
+    inputs.sort_by_key(|input| input.frame);
+
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(
+            "game.gb --frames 30 --until-serial Passed --filter scale2x",
+        ))
+        .unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.frames, 30);
+        assert_eq!(options.until_serial.as_deref(), Some("Passed"));
+        assert_eq!(options.filter, VideoFilter::Scale2x);
+
+        let options = parse_args(args("game.gb")).unwrap();
+        assert_eq!(options.frames, DEFAULT_FRAMES);
+        assert_eq!(options.filter, VideoFilter::None);
+        assert_eq!(options.colorize, None);
+
+        let options = parse_args(args("game.gb --colorize")).unwrap();
+        assert_eq!(options.colorize, Some(None));
+        let options = parse_args(args("game.gb --colorize=Left+B")).unwrap();
+        assert_eq!(options.colorize, Some(Some(ButtonCombo::LeftB)));
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        // all of these make main() exit with EXIT_ERROR
+        assert!(parse_args(args("")).is_err());
+        assert!(parse_args(args("game.gb --frames")).is_err());
+        assert!(parse_args(args("game.gb --frames many")).is_err());
+        assert!(parse_args(args("game.gb --filter blurry")).is_err());
+        assert!(parse_args(args("game.gb --turbo")).is_err());
+        assert!(parse_args(args("game.gb --colorize=up+select")).is_err());
+        assert!(parse_args(args(
+            "game.gb --link-listen 127.0.0.1:8765 --link-connect 127.0.0.1:8765"
+        ))
+        .is_err());
+        assert!(parse_args(args("game.gb --printer out --link-listen 127.0.0.1:8765")).is_err());
+        assert!(parse_args(args("game.gb --link-connect 127.0.0.1:8765 --printer out")).is_err());
+
+        assert!(parse_args(args("game.gb --link-listen 127.0.0.1:8765")).is_ok());
+        assert!(parse_args(args("game.gb --printer out")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_inputs() {
+        let inputs = parse_inputs(
+            "# title screen\n\
+             120 press start\n\
+             \n\
+             10 press A\n\
+             125 release start\n",
+        )
+        .unwrap();
+
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs[0].frame, 10);
+        assert!(inputs[0].pressed);
+        assert_eq!(inputs[0].key, Key::BtnA);
+        assert_eq!(inputs[1].frame, 120);
+        assert_eq!(inputs[1].key, Key::Start);
+        assert_eq!(inputs[2].frame, 125);
+        assert!(!inputs[2].pressed);
+    }
+
+    #[test]
+    fn test_parse_inputs_errors() {
+        assert_eq!(
+            parse_inputs("1 press a\n2 hold b").err().unwrap(),
+            "Invalid input on line 2: 2 hold b"
+        );
+        assert!(parse_inputs("1 press turbo").is_err());
+        assert!(parse_inputs("soon press a").is_err());
+        assert!(parse_inputs("1 press").is_err());
+        assert!(parse_inputs("1 press a b").is_err());
+    }
+}
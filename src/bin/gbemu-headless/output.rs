@@ -0,0 +1,255 @@
+// Minimal PNG and WAV writers so the runner doesn't need any image or audio crates.
+// The PNG is written with uncompressed (stored) deflate blocks, which keeps the
+// encoder tiny at the cost of file size.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let mut raw = Vec::with_capacity(height * (width * 4 + 1));
+    for row in rgba.chunks(width * 4).take(height) {
+        // filter type 0 (None)
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 8, colour type 6 (RGBA), default compression, filter and interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A])?;
+    write_chunk(&mut file, b"IHDR", &ihdr)?;
+    write_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+pub fn write_wav(path: &Path, sample_rate: u32, left: &[f32], right: &[f32]) -> io::Result<()> {
+    let frames = left.len().min(right.len()) as u32;
+    let data_len = frames * 4;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    // PCM, 2 channels
+    file.write_all(&1u16.to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?;
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&(sample_rate * 4).to_le_bytes())?;
+    file.write_all(&4u16.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+
+    for (l, r) in left.iter().zip(right.iter()) {
+        file.write_all(&to_pcm(*l).to_le_bytes())?;
+        file.write_all(&to_pcm(*r).to_le_bytes())?;
+    }
+
+    file.flush()
+}
+
+fn to_pcm(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    out.write_all(&crc.finish().to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+    out
+}
+
+struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let mut table = [0u32; 256];
+
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+
+        Self {
+            table,
+            value: 0xFFFF_FFFF,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.value =
+                self.table[((self.value ^ *byte as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.value ^ 0xFFFF_FFFF
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn be32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn le32(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finish()
+    }
+
+    #[test]
+    fn test_crc32() {
+        // check value from the CRC catalogue and the CRC of a bare IEND chunk
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        // Adler-32 of "Wikipedia" is the usual example
+        let data = zlib_stored(b"Wikipedia");
+        assert_eq!(&data[..2], &[0x78, 0x01]);
+        assert_eq!(&data[2..7], &[1, 9, 0, 0xF6, 0xFF]);
+        assert_eq!(&data[7..16], b"Wikipedia");
+        assert_eq!(be32(&data[16..]), 0x11E6_0398);
+
+        assert_eq!(
+            zlib_stored(&[]),
+            [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]
+        );
+
+        // more than one stored block, only the last has BFINAL set
+        let data = zlib_stored(&vec![0xAB; 0x10000]);
+        assert_eq!(&data[2..7], &[0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(&data[0x10006..0x1000B], &[1, 1, 0, 0xFE, 0xFF]);
+        assert_eq!(data.len(), 2 + 5 + 0xFFFF + 5 + 1 + 4);
+    }
+
+    #[test]
+    fn test_write_png() {
+        let path = env::temp_dir().join(format!("gbemu-test-{}.png", std::process::id()));
+        let pixels = [
+            0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, //
+            0x00, 0x00, 0xFF, 0xFF, 0x10, 0x20, 0x30, 0x40,
+        ];
+        write_png(&path, 2, 2, &pixels).unwrap();
+        let png = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = be32(&png[pos..]) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            assert_eq!(be32(&png[pos + 8 + len..]), crc32(body));
+            chunks.push((body[..4].to_vec(), body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        assert_eq!(pos, png.len());
+
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+
+        // a single stored block holding both rows behind filter type 0
+        let idat = &chunks[1].1;
+        assert_eq!(&idat[..7], &[0x78, 0x01, 1, 18, 0, !18, 0xFF]);
+        let raw = &idat[7..idat.len() - 4];
+        assert_eq!(raw[0], 0);
+        assert_eq!(&raw[1..9], &pixels[..8]);
+        assert_eq!(raw[9], 0);
+        assert_eq!(&raw[10..], &pixels[8..]);
+
+        let (mut a, mut b) = (1u32, 0u32);
+        for byte in raw {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        assert_eq!(be32(&idat[idat.len() - 4..]), (b << 16) | a);
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let path = env::temp_dir().join(format!("gbemu-test-{}.wav", std::process::id()));
+        write_wav(&path, 48000, &[0.0, 1.0, -2.0], &[0.5, -1.0]).unwrap();
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(le32(&wav[4..]), 36 + 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(le32(&wav[24..]), 48000);
+        assert_eq!(le32(&wav[28..]), 48000 * 4);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(le32(&wav[40..]), 8);
+
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(samples, [0, 16383, 32767, -32767]);
+    }
+}
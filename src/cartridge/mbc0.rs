@@ -2,6 +2,7 @@ use crate::cartridge::Mbc;
 
 const RAM_SIZE: usize = 0x2000;
 const RAM_OFFSET: usize = 0xA000;
+const ROM_BANK_SIZE: usize = 0x4000;
 
 pub struct Mbc0 {
     rom: Vec<u8>,
@@ -33,4 +34,8 @@ impl Mbc for Mbc0 {
             _ => panic!(format!("Address {:#X} out of bounds.", addr)),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        (addr as usize) / ROM_BANK_SIZE
+    }
 }
@@ -120,4 +120,14 @@ impl Mbc for Mbc1 {
             _ => panic!("Address out of bounds."),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank = match (addr, &self.mode) {
+            (0x0000..=0x3FFF, Mode::Mode0) => 0,
+            (0x0000..=0x3FFF, Mode::Mode1) => self.bank2 << 5,
+            _ => (self.bank2 << 5) | self.bank1,
+        };
+
+        (bank as usize * ROM_BANK_SIZE % self.size) / ROM_BANK_SIZE
+    }
 }
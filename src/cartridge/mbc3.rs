@@ -183,4 +183,11 @@ impl Mbc for Mbc3 {
             _ => panic!("Address out of bounds {:#X}.", addr),
         }
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
 }
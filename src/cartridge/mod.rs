@@ -6,6 +6,8 @@ pub mod mbc5;
 pub trait Mbc {
     fn get_byte(&mut self, addr: u16) -> u8;
     fn set_byte(&mut self, addr: u16, value: u8);
+    /// The ROM bank currently mapped at `addr` (0000-7FFF).
+    fn rom_bank(&self, addr: u16) -> usize;
 }
 
 use crate::cartridge::mbc0::Mbc0;
@@ -37,4 +39,11 @@ impl Cartridge {
     pub fn set_byte(&mut self, addr: u16, value: u8) {
         self.mbc.set_byte(addr, value);
     }
+
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.rom_bank(addr),
+            _ => 0,
+        }
+    }
 }
@@ -0,0 +1,343 @@
+// Cached interpreter. Straight-line runs of code (basic blocks) are decoded once into
+// a list of instructions with their operand bytes already read, keyed by the memory
+// mapped at the block's start (see `Bus::code_bank`). Executing from the cache skips
+// the bus reads for opcodes and immediates, but still goes through `decode_exec`
+// and still calls `add_cycles` for every fetch, so timing is unchanged.
+//
+// Blocks in ROM are keyed by ROM bank and never go stale. Blocks in WRAM and HRAM
+// are thrown away as soon as the cpu writes to memory they were decoded from. Any
+// write that may remap memory (MBC registers, boot ROM disable, WRAM bank) ends the
+// block being executed so the next instruction is looked up again.
+//
+// Operand reads served from the cache don't show up in the access monitor.
+
+use crate::memory::bus::Bus;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const MAX_BLOCK_LEN: usize = 64;
+const RAM_START: u16 = 0xC000;
+const ECHO_START: u16 = 0xE000;
+const ECHO_END: u16 = 0xFDFF;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub operands: [u8; 2],
+    pub len: u8,
+}
+
+struct Block {
+    instructions: Vec<Instruction>,
+}
+
+struct Cursor {
+    block: Rc<Block>,
+    index: usize,
+    pc: u16,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub blocks_decoded: u64,
+    pub invalidations: u64,
+}
+
+pub struct BlockCache {
+    blocks: HashMap<(usize, u16), Rc<Block>>,
+    // Addresses from C000 up that belong to a cached block.
+    ram_code: Vec<bool>,
+    has_ram_blocks: bool,
+    cursor: Option<Cursor>,
+    stats: CacheStats,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new()
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            ram_code: vec![false; 0x10000 - RAM_START as usize],
+            has_ram_blocks: false,
+            cursor: None,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// The decoded instruction at `pc`, or None if code there isn't cacheable.
+    pub fn next<B: Bus>(&mut self, pc: u16, bus: &mut B) -> Option<Instruction> {
+        if let Some(cursor) = &mut self.cursor {
+            if cursor.pc == pc && cursor.index < cursor.block.instructions.len() {
+                let instruction = cursor.block.instructions[cursor.index];
+                cursor.index += 1;
+                cursor.pc = pc.wrapping_add(instruction.len as u16);
+                self.stats.hits += 1;
+                return Some(instruction);
+            }
+        }
+
+        self.cursor = None;
+
+        let bank = match bus.code_bank(pc) {
+            Some(bank) => bank,
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+
+        let block = match self.blocks.get(&(bank, pc)) {
+            Some(block) => block.clone(),
+            None => {
+                let block = Rc::new(self.decode(bus, bank, pc)?);
+                self.blocks.insert((bank, pc), block.clone());
+                self.stats.blocks_decoded += 1;
+                block
+            }
+        };
+
+        let instruction = block.instructions[0];
+        self.cursor = Some(Cursor {
+            block,
+            index: 1,
+            pc: pc.wrapping_add(instruction.len as u16),
+        });
+        self.stats.misses += 1;
+
+        Some(instruction)
+    }
+
+    /// Called for every cpu write.
+    pub fn written(&mut self, addr: u16) {
+        if !(0x8000..0xFF00).contains(&addr) {
+            self.cursor = None;
+        }
+
+        let addr = match addr {
+            ECHO_START..=ECHO_END => addr - (ECHO_START - RAM_START),
+            _ => addr,
+        };
+
+        if self.has_ram_blocks && addr >= RAM_START && self.ram_code[(addr - RAM_START) as usize] {
+            self.flush_ram_blocks();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = BlockCache::new();
+    }
+
+    fn flush_ram_blocks(&mut self) {
+        self.blocks.retain(|(_, pc), _| *pc < RAM_START);
+
+        for marked in self.ram_code.iter_mut() {
+            *marked = false;
+        }
+
+        self.has_ram_blocks = false;
+        self.cursor = None;
+        self.stats.invalidations += 1;
+    }
+
+    fn decode<B: Bus>(&mut self, bus: &mut B, bank: usize, start: u16) -> Option<Block> {
+        let mut instructions = Vec::new();
+        let mut pc = start;
+
+        while instructions.len() < MAX_BLOCK_LEN {
+            let opcode = bus.code_byte(pc);
+            let len = instruction_len(opcode);
+
+            // Stop before an instruction that runs into differently mapped memory.
+            let fits = (0..len as u16).all(|i| match pc.checked_add(i) {
+                Some(addr) => bus.code_bank(addr) == Some(bank),
+                None => false,
+            });
+            if !fits {
+                break;
+            }
+
+            let mut operands = [0; 2];
+            for i in 1..len as u16 {
+                operands[i as usize - 1] = bus.code_byte(pc + i);
+            }
+
+            instructions.push(Instruction {
+                opcode,
+                operands,
+                len,
+            });
+
+            if pc >= RAM_START {
+                for i in 0..len as u16 {
+                    self.ram_code[(pc + i - RAM_START) as usize] = true;
+                }
+                self.has_ram_blocks = true;
+            }
+
+            pc = pc.wrapping_add(len as u16);
+
+            if ends_block(opcode) {
+                break;
+            }
+        }
+
+        if instructions.is_empty() {
+            None
+        } else {
+            Some(Block { instructions })
+        }
+    }
+}
+
+fn instruction_len(opcode: u8) -> u8 {
+    match opcode {
+        0x01 | 0x08 | 0x11 | 0x21 | 0x31 => 3,
+        0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2 | 0xD4 | 0xDA | 0xDC => 3,
+        0xEA | 0xFA => 3,
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 2,
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        0xCB | 0xE0 | 0xE8 | 0xF0 | 0xF8 => 2,
+        _ => 1,
+    }
+}
+
+// Jumps, calls, returns, HALT/STOP and the invalid opcodes.
+const BLOCK_ENDS: [u8; 43] = [
+    0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x76, 0xC0, 0xC2, 0xC3, 0xC4, 0xC7, 0xC8, 0xC9, 0xCA, 0xCC,
+    0xCD, 0xCF, 0xD0, 0xD2, 0xD4, 0xD7, 0xD8, 0xD9, 0xDA, 0xDC, 0xDF, 0xE7, 0xE9, 0xEF, 0xF7, 0xFF,
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+fn ends_block(opcode: u8) -> bool {
+    BLOCK_ENDS.contains(&opcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, R8};
+    use crate::memory::bus::FlatBus;
+
+    // A flat bus where everything from 0000 is cacheable as bank 0.
+    struct CodeBus(FlatBus);
+
+    impl Bus for CodeBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.0.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.0.write(addr, value)
+        }
+
+        fn tick(&mut self, _cycles: usize) {}
+
+        fn code_bank(&self, _addr: u16) -> Option<usize> {
+            Some(0)
+        }
+    }
+
+    #[test]
+    fn test_block_decoding() {
+        // ld a,$12; ld ($C000),a; jr -7; nop
+        let mut bus = CodeBus(FlatBus::with_program(
+            0x0100,
+            &[0x3E, 0x12, 0xEA, 0x00, 0xC0, 0x18, 0xF9, 0x00],
+        ));
+        let mut cache = BlockCache::new();
+
+        let first = cache.next(0x0100, &mut bus).unwrap();
+        assert_eq!(
+            (first.opcode, first.operands[0], first.len),
+            (0x3E, 0x12, 2)
+        );
+
+        let second = cache.next(0x0102, &mut bus).unwrap();
+        assert_eq!(
+            (second.opcode, second.operands, second.len),
+            (0xEA, [0x00, 0xC0], 3)
+        );
+
+        cache.next(0x0105, &mut bus).unwrap();
+        cache.next(0x0100, &mut bus).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.blocks_decoded, 1);
+        assert_eq!(stats.hits, 2);
+    }
+
+    #[test]
+    fn test_ram_write_invalidates() {
+        // inc a; ret
+        let mut bus = CodeBus(FlatBus::with_program(0xC100, &[0x3C, 0xC9]));
+        let mut cache = BlockCache::new();
+
+        assert_eq!(cache.next(0xC100, &mut bus).unwrap().opcode, 0x3C);
+
+        // A write to the echo of C101 replaces the RET.
+        bus.write(0xC101, 0x00);
+        cache.written(0xE101);
+        assert_eq!(cache.stats().invalidations, 1);
+
+        cache.next(0xC100, &mut bus).unwrap();
+        assert_eq!(cache.next(0xC101, &mut bus).unwrap().opcode, 0x00);
+        assert_eq!(cache.stats().blocks_decoded, 2);
+    }
+
+    fn self_modifying_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // nop; jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // Copies `inc b; ret` to C000 and calls it twice, then patches it to `inc c`
+        // and calls it once more.
+        rom[0x150..0x16B].copy_from_slice(&[
+            0x01, 0x00, 0x00, // ld bc,$0000
+            0x21, 0x00, 0xC0, // ld hl,$C000
+            0x36, 0x04, // ld (hl),$04
+            0x23, // inc hl
+            0x36, 0xC9, // ld (hl),$C9
+            0xCD, 0x00, 0xC0, // call $C000
+            0xCD, 0x00, 0xC0, // call $C000
+            0x3E, 0x0C, // ld a,$0C
+            0xEA, 0x00, 0xC0, // ld ($C000),a
+            0xCD, 0x00, 0xC0, // call $C000
+            0x18, 0xFE, // jr -2
+        ]);
+        rom
+    }
+
+    #[test]
+    fn test_matches_uncached_cpu() {
+        let mut uncached = Cpu::new(self_modifying_rom());
+        uncached.simulate_bootrom();
+
+        let mut cached = Cpu::new(self_modifying_rom());
+        cached.simulate_bootrom();
+        cached.enable_block_cache();
+
+        for _ in 0..100 {
+            assert_eq!(cached.tick(), uncached.tick());
+            assert_eq!(cached.pc, uncached.pc);
+        }
+
+        assert_eq!(cached.get_r8(&R8::B), 2);
+        assert_eq!(cached.get_r8(&R8::C), 1);
+        assert_eq!(uncached.get_r8(&R8::C), 1);
+
+        let stats = cached.block_cache_stats().unwrap();
+        assert!(stats.hits > 0);
+        assert_eq!(stats.invalidations, 1);
+    }
+}
@@ -1,10 +1,14 @@
 // References: https://github.com/LIJI32/SameBoy/blob/master/Core/sm83_cpu.c
 
+pub mod cache;
 pub mod opcodes;
 
+use crate::cpu::cache::{BlockCache, CacheStats};
 use crate::events::Event;
 use crate::joypad::Key;
-use crate::memory::mmu::{HdmaType, Mmu};
+pub use crate::memory::bus::{Bus, FlatBus};
+use crate::memory::mmu::Mmu;
+use crate::profiler::Profiler;
 
 const MAX_CYCLES: usize = 69905;
 
@@ -82,11 +86,11 @@ pub enum Flag {
     C,
 }
 
-pub struct Cpu {
+pub struct Cpu<B: Bus = Mmu> {
     r: [u8; 8],
     pub pc: u16,
     sp: u16,
-    pub mmu: Mmu,
+    pub mmu: B,
     pub cycles: usize,
     ime: bool,
     halted: bool,
@@ -98,9 +102,17 @@ pub struct Cpu {
 
     event_cycles: usize,
     audio_flag: bool,
+
+    profiler: Option<Profiler>,
+
+    block_cache: Option<BlockCache>,
+    // Operand bytes of the current instruction when it came from the block cache.
+    operands: [u8; 2],
+    operands_len: u8,
+    operand_index: u8,
 }
 
-impl Cpu {
+impl Cpu<Mmu> {
     pub fn new(data: Vec<u8>) -> Self {
         let emu_mode = if (data[0x0143] & 0x80) != 0 {
             EmulationMode::Cgb
@@ -108,22 +120,7 @@ impl Cpu {
             EmulationMode::Dmg
         };
 
-        Cpu {
-            r: [0; 8],
-            pc: 0,
-            sp: 0,
-            mmu: Mmu::new(data, emu_mode.clone()),
-            cycles: 0,
-            ime: true,
-            halted: false,
-            emu_mode,
-            stopped: false,
-            halt_bug: false,
-            ime_set_pending: false,
-            just_halted: false,
-            event_cycles: 0,
-            audio_flag: true,
-        }
+        Cpu::with_bus(Mmu::new(data, emu_mode.clone()), emu_mode)
     }
 
     pub fn keydown(&mut self, key: usize) {
@@ -188,6 +185,146 @@ impl Cpu {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn emulate_bootrom(&mut self) {
+        self.mmu.bootrom.activate();
+    }
+
+    pub fn simulate_bootrom(&mut self) {
+        match self.emu_mode {
+            EmulationMode::Dmg => {
+                // AF = 0x01B0
+                self.r[0] = 0x01;
+                self.r[1] = 0xB0;
+                // BC = 0x0013
+                self.r[2] = 0x00;
+                self.r[3] = 0x13;
+                // DE = 0x00D8
+                self.r[4] = 0x00;
+                self.r[5] = 0xD8;
+                // HL = 0x014D
+                self.r[6] = 0x01;
+                self.r[7] = 0x4D;
+            }
+            EmulationMode::Cgb => {
+                // AF = 0x1180;
+                self.r[0] = 0x11;
+                self.r[1] = 0x80;
+                // BC = 0x0000;
+                self.r[2] = 0x00;
+                self.r[3] = 0x00;
+                // DE = 0xFF56;
+                self.r[4] = 0xFF;
+                self.r[5] = 0x56;
+                // HL = 0x000D;
+                self.r[6] = 0x00;
+                self.r[7] = 0x0D;
+            }
+        }
+
+        self.sp = 0xFFFE;
+
+        self.mmu.set_byte(0xFF05, 0x00);
+        self.mmu.set_byte(0xFF06, 0x00);
+        self.mmu.set_byte(0xFF07, 0x00);
+        self.mmu.set_byte(0xFF10, 0x80);
+        self.mmu.set_byte(0xFF11, 0xBF);
+        self.mmu.set_byte(0xFF12, 0xF3);
+        self.mmu.set_byte(0xFF14, 0xBF);
+        self.mmu.set_byte(0xFF16, 0x3F);
+        self.mmu.set_byte(0xFF17, 0x00);
+        self.mmu.set_byte(0xFF19, 0xBF);
+        self.mmu.set_byte(0xFF1A, 0x7F);
+        self.mmu.set_byte(0xFF1B, 0xFF);
+        self.mmu.set_byte(0xFF1C, 0x9F);
+        self.mmu.set_byte(0xFF1E, 0xBF);
+        self.mmu.set_byte(0xFF20, 0xFF);
+        self.mmu.set_byte(0xFF21, 0x00);
+        self.mmu.set_byte(0xFF22, 0x00);
+        self.mmu.set_byte(0xFF23, 0xBF);
+        self.mmu.set_byte(0xFF24, 0x77);
+        self.mmu.set_byte(0xFF25, 0xF3);
+        self.mmu.set_byte(0xFF26, 0xF1);
+
+        self.mmu.set_byte(0xFF40, 0x91);
+        self.mmu.set_byte(0xFF41, 0x81);
+        self.mmu.set_byte(0xFF42, 0x00);
+        self.mmu.set_byte(0xFF43, 0x00);
+        self.mmu.set_byte(0xFF45, 0x00);
+        self.mmu.set_byte(0xFF47, 0xFC);
+        self.mmu.set_byte(0xFF48, 0xFF);
+        self.mmu.set_byte(0xFF49, 0xFF);
+        self.mmu.set_byte(0xFF4A, 0x00);
+        self.mmu.set_byte(0xFF4B, 0x00);
+        self.mmu.set_byte(0xFFFF, 0x00);
+
+        self.pc = 0x100;
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// A cpu running against any bus, starting from a zeroed register file.
+    pub fn with_bus(mmu: B, emu_mode: EmulationMode) -> Self {
+        Cpu {
+            r: [0; 8],
+            pc: 0,
+            sp: 0,
+            mmu,
+            cycles: 0,
+            ime: true,
+            halted: false,
+            emu_mode,
+            stopped: false,
+            halt_bug: false,
+            ime_set_pending: false,
+            just_halted: false,
+            event_cycles: 0,
+            audio_flag: true,
+            profiler: None,
+            block_cache: None,
+            operands: [0; 2],
+            operands_len: 0,
+            operand_index: 0,
+        }
+    }
+
+    fn leave_stop_mode(&mut self) {
+        for _ in 0..0x200 {
+            self.add_cycles(0x10);
+        }
+
+        self.stopped = false;
+    }
+
+    pub fn enable_profiler(&mut self) {
+        if self.profiler.is_none() {
+            self.profiler = Some(Profiler::new());
+        }
+    }
+
+    /// Switches to the cached interpreter (see `cache.rs`).
+    pub fn enable_block_cache(&mut self) {
+        if self.block_cache.is_none() {
+            self.block_cache = Some(BlockCache::new());
+        }
+    }
+
+    pub fn disable_block_cache(&mut self) {
+        self.block_cache = None;
+    }
+
+    pub fn block_cache_stats(&self) -> Option<CacheStats> {
+        self.block_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    pub fn disable_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
     pub fn tick(&mut self) -> usize {
         self.cycles = 0;
 
@@ -199,10 +336,15 @@ impl Cpu {
             return self.halt_tick();
         }
 
-        match self.mmu.hdma.hdma_type {
-            HdmaType::GPDma => self.gdma_tick(),
-            HdmaType::HBlankDma if self.mmu.gpu.hdma_flag => self.hdma_tick(),
-            _ => self.cpu_tick(),
+        match self.mmu.hdma_transfer() {
+            Some(cycles) => {
+                self.add_cycles(cycles);
+
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_dma(self.cycles);
+                }
+            }
+            None => self.cpu_tick(),
         }
 
         self.cycles
@@ -211,10 +353,7 @@ impl Cpu {
     fn cpu_tick(&mut self) {
         self.just_halted = false;
 
-        let ie = self.mmu.ie;
-        let irr = self.mmu.get_byte(0xFF0F);
-
-        let ints_pending = ie & irr & 0x1F;
+        let ints_pending = self.mmu.pending_interrupts();
 
         let ime = self.ime;
         if self.ime_set_pending {
@@ -227,7 +366,20 @@ impl Cpu {
             return;
         }
 
-        let opcode = self.fetch();
+        let pc = self.pc;
+        self.mmu.record_execute(pc);
+
+        let opcode = match self.cached_instruction(pc) {
+            Some(instruction) => {
+                self.operands = instruction.operands;
+                self.operands_len = instruction.len - 1;
+                self.operand_index = 0;
+                self.pc = pc.wrapping_add(1);
+                self.add_cycles(4);
+                instruction.opcode
+            }
+            None => self.fetch(),
+        };
 
         if self.halt_bug {
             self.halt_bug = false;
@@ -235,6 +387,23 @@ impl Cpu {
         }
 
         self.decode_exec(opcode);
+        self.operands_len = 0;
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_instruction(self.mmu.rom_bank(pc), pc, self.cycles);
+        }
+    }
+
+    fn cached_instruction(&mut self, pc: u16) -> Option<cache::Instruction> {
+        // The HALT bug reads the next opcode twice, leave that to the bus.
+        if self.halt_bug {
+            return None;
+        }
+
+        match &mut self.block_cache {
+            Some(cache) => cache.next(pc, &mut self.mmu),
+            None => None,
+        }
     }
 
     fn halt_tick(&mut self) -> usize {
@@ -242,9 +411,7 @@ impl Cpu {
             self.add_cycles(2);
         }
 
-        let irr = self.mmu.get_byte(0xFF0F);
-
-        let ints_pending = self.mmu.ie & irr & 0x1F;
+        let ints_pending = self.mmu.pending_interrupts();
 
         self.add_cycles(if self.emu_mode == EmulationMode::Cgb || self.just_halted {
             4
@@ -254,6 +421,10 @@ impl Cpu {
 
         self.just_halted = false;
 
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_halt(self.cycles);
+        }
+
         let ime = self.ime;
         if self.ime_set_pending {
             self.ime = !self.ime;
@@ -273,29 +444,16 @@ impl Cpu {
 
     fn stop_tick(&mut self) -> usize {
         self.add_cycles(4);
-        if self.mmu.get_byte(0xFF00) & 0xF != 0xF {
+        if self.mmu.read(0xFF00) & 0xF != 0xF {
             self.leave_stop_mode();
             self.add_cycles(8);
         }
-        self.cycles
-    }
-
-    fn gdma_tick(&mut self) {
-        self.mmu.gdma_tick();
-
-        self.add_cycles(match self.mmu.cgb_mode.speed {
-            CgbSpeed::Normal => 32,
-            CgbSpeed::Double => 64,
-        });
-    }
 
-    fn hdma_tick(&mut self) {
-        self.mmu.hdma_tick();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_stop(self.cycles);
+        }
 
-        self.add_cycles(match self.mmu.cgb_mode.speed {
-            CgbSpeed::Normal => 32,
-            CgbSpeed::Double => 64,
-        });
+        self.cycles
     }
 
     fn service_pending_interrupts(&mut self) {
@@ -305,27 +463,31 @@ impl Cpu {
         // other branches of the repository.
         self.halted = false;
 
+        let start_cycles = self.cycles;
+
         self.sp = self.sp.wrapping_sub(1);
 
         self.add_cycles(12);
 
-        self.mmu.set_byte(self.sp, (self.pc >> 8) as u8);
+        self.mmu.write(self.sp, (self.pc >> 8) as u8);
+        self.code_written(self.sp);
 
-        let mut ints = self.mmu.ie;
+        let mut ints = self.mmu.interrupt_enable();
 
         if self.sp == 0xFF0F + 1 {
             self.sp = self.sp.wrapping_sub(1);
             self.add_cycles(4);
-            let old_irr = self.mmu.get_byte(0xFF0F);
-            self.mmu.set_byte(0xFF0F, (self.pc & 0xFF) as u8);
+            let old_irr = self.mmu.read(0xFF0F);
+            self.mmu.write(0xFF0F, (self.pc & 0xFF) as u8);
 
             ints &= old_irr & 0x1F;
         } else {
             self.sp = self.sp.wrapping_sub(1);
             self.add_cycles(4);
-            self.mmu.set_byte(self.sp, (self.pc & 0xFF) as u8);
+            self.mmu.write(self.sp, (self.pc & 0xFF) as u8);
+            self.code_written(self.sp);
 
-            ints &= self.mmu.get_byte(0xFF0F) & 0x1F;
+            ints &= self.mmu.read(0xFF0F) & 0x1F;
         }
 
         self.add_cycles(4);
@@ -338,7 +500,13 @@ impl Cpu {
 
         for i in 0..5 {
             if ints & (1u8 << i) != 0 {
-                return self.handle_interrupt(i);
+                self.handle_interrupt(i);
+
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.enter_interrupt(i as usize, self.sp, self.cycles - start_cycles);
+                }
+
+                return;
             }
         }
     }
@@ -346,95 +514,11 @@ impl Cpu {
     fn handle_interrupt(&mut self, i: u16) {
         let mask = 1u8 << i;
         self.ime = false;
-        let irr = self.mmu.get_byte(0xFF0F);
-        self.mmu.set_byte(0xFF0F, irr & !mask);
+        let irr = self.mmu.read(0xFF0F);
+        self.mmu.write(0xFF0F, irr & !mask);
         self.pc = 0x40 + 8 * i;
     }
 
-    fn leave_stop_mode(&mut self) {
-        for _ in 0..0x200 {
-            self.add_cycles(0x10);
-        }
-
-        self.stopped = false;
-    }
-
-    #[allow(dead_code)]
-    pub fn emulate_bootrom(&mut self) {
-        self.mmu.bootrom.activate();
-    }
-
-    pub fn simulate_bootrom(&mut self) {
-        match self.emu_mode {
-            EmulationMode::Dmg => {
-                // AF = 0x01B0
-                self.r[0] = 0x01;
-                self.r[1] = 0xB0;
-                // BC = 0x0013
-                self.r[2] = 0x00;
-                self.r[3] = 0x13;
-                // DE = 0x00D8
-                self.r[4] = 0x00;
-                self.r[5] = 0xD8;
-                // HL = 0x014D
-                self.r[6] = 0x01;
-                self.r[7] = 0x4D;
-            }
-            EmulationMode::Cgb => {
-                // AF = 0x1180;
-                self.r[0] = 0x11;
-                self.r[1] = 0x80;
-                // BC = 0x0000;
-                self.r[2] = 0x00;
-                self.r[3] = 0x00;
-                // DE = 0xFF56;
-                self.r[4] = 0xFF;
-                self.r[5] = 0x56;
-                // HL = 0x000D;
-                self.r[6] = 0x00;
-                self.r[7] = 0x0D;
-            }
-        }
-
-        self.sp = 0xFFFE;
-
-        self.mmu.set_byte(0xFF05, 0x00);
-        self.mmu.set_byte(0xFF06, 0x00);
-        self.mmu.set_byte(0xFF07, 0x00);
-        self.mmu.set_byte(0xFF10, 0x80);
-        self.mmu.set_byte(0xFF11, 0xBF);
-        self.mmu.set_byte(0xFF12, 0xF3);
-        self.mmu.set_byte(0xFF14, 0xBF);
-        self.mmu.set_byte(0xFF16, 0x3F);
-        self.mmu.set_byte(0xFF17, 0x00);
-        self.mmu.set_byte(0xFF19, 0xBF);
-        self.mmu.set_byte(0xFF1A, 0x7F);
-        self.mmu.set_byte(0xFF1B, 0xFF);
-        self.mmu.set_byte(0xFF1C, 0x9F);
-        self.mmu.set_byte(0xFF1E, 0xBF);
-        self.mmu.set_byte(0xFF20, 0xFF);
-        self.mmu.set_byte(0xFF21, 0x00);
-        self.mmu.set_byte(0xFF22, 0x00);
-        self.mmu.set_byte(0xFF23, 0xBF);
-        self.mmu.set_byte(0xFF24, 0x77);
-        self.mmu.set_byte(0xFF25, 0xF3);
-        self.mmu.set_byte(0xFF26, 0xF1);
-
-        self.mmu.set_byte(0xFF40, 0x91);
-        self.mmu.set_byte(0xFF41, 0x81);
-        self.mmu.set_byte(0xFF42, 0x00);
-        self.mmu.set_byte(0xFF43, 0x00);
-        self.mmu.set_byte(0xFF45, 0x00);
-        self.mmu.set_byte(0xFF47, 0xFC);
-        self.mmu.set_byte(0xFF48, 0xFF);
-        self.mmu.set_byte(0xFF49, 0xFF);
-        self.mmu.set_byte(0xFF4A, 0x00);
-        self.mmu.set_byte(0xFF4B, 0x00);
-        self.mmu.set_byte(0xFFFF, 0x00);
-
-        self.pc = 0x100;
-    }
-
     // -------------------------------------------------------------
     //  Restarts & Returns
     // -------------------------------------------------------------
@@ -445,6 +529,10 @@ impl Cpu {
     }
 
     pub fn ret(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.leave_call(self.sp);
+        }
+
         self.pc = self.pop() as u16;
         self.pc |= (self.pop() as u16) << 8;
         self.add_cycles(4);
@@ -470,6 +558,10 @@ impl Cpu {
         self.push((self.pc >> 8) as u8);
         self.push((self.pc & 0xFF) as u8);
         self.pc = addr as u16;
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter_call(self.mmu.rom_bank(addr), addr, self.sp);
+        }
     }
 
     pub fn call(&mut self) {
@@ -764,15 +856,21 @@ impl Cpu {
         self.ime = false;
     }
 
+    /// The interrupt master enable, counting an EI whose delay hasn't run out yet.
+    pub fn ime(&self) -> bool {
+        self.ime || self.ime_set_pending
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.ime_set_pending = false;
+    }
+
     pub fn stop(&mut self) {
         self.stopped = true;
+        self.mmu.enter_stop();
 
-        if self.mmu.cgb_mode.prepare_speed_switch != 0x0 {
-            self.mmu.cgb_mode.speed = match self.mmu.cgb_mode.speed {
-                CgbSpeed::Normal => CgbSpeed::Double,
-                CgbSpeed::Double => CgbSpeed::Normal,
-            };
-            self.mmu.cgb_mode.prepare_speed_switch = 0x0;
+        if self.mmu.switch_speed() {
             self.leave_stop_mode();
         }
     }
@@ -780,10 +878,7 @@ impl Cpu {
     pub fn halt(&mut self) {
         self.halted = true;
 
-        let ie = self.mmu.ie;
-        let irr = self.mmu.get_byte(0xFF0F);
-
-        if ie & irr & 0x1F != 0 {
+        if self.mmu.pending_interrupts() != 0 {
             if self.ime {
                 self.halted = false;
                 self.pc -= 1;
@@ -1277,27 +1372,22 @@ impl Cpu {
     fn add_cycles(&mut self, cycles: usize) {
         self.cycles += cycles;
 
-        self.mmu.timer_tick(cycles);
-
-        if !self.stopped && !self.halted && self.mmu.oam_dma.active {
-            self.mmu.oam_dma_tick(cycles);
+        if !self.stopped && !self.halted {
+            self.mmu.dma_tick(cycles);
         }
 
-        match self.mmu.cgb_mode.speed {
-            CgbSpeed::Normal => {
-                self.mmu.gpu_tick(cycles);
-                self.mmu.apu_tick(cycles);
-            }
-            CgbSpeed::Double => {
-                self.mmu.gpu_tick(cycles >> 1);
-                self.mmu.apu_tick(cycles >> 1);
-            }
-        }
+        self.mmu.tick(cycles);
     }
 
     /// Fetch next byte at pc from memory and increment pc.
     pub fn fetch(&mut self) -> u8 {
-        let byte = self.mmu.get_byte(self.pc);
+        let byte = if self.operand_index < self.operands_len {
+            let byte = self.operands[self.operand_index as usize];
+            self.operand_index += 1;
+            byte
+        } else {
+            self.mmu.read(self.pc)
+        };
         self.pc = self.pc.wrapping_add(1);
         self.add_cycles(4);
         byte
@@ -1396,33 +1486,21 @@ impl Cpu {
     }
 
     pub fn memory_set(&mut self, addr: u16, value: u8) {
-        self.mmu.set_byte(addr, value);
+        self.mmu.write(addr, value);
+        self.code_written(addr);
         self.add_cycles(4);
     }
 
+    #[inline]
+    fn code_written(&mut self, addr: u16) {
+        if let Some(cache) = &mut self.block_cache {
+            cache.written(addr);
+        }
+    }
+
     pub fn memory_get(&mut self, addr: u16) -> u8 {
-        let value = self.mmu.get_byte(addr);
+        let value = self.mmu.read(addr);
         self.add_cycles(4);
         value
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-
-    #[test]
-    fn test_rom() {
-        let rom = fs::read("roms/<example_rom>").unwrap();
-
-        let mut cpu = Cpu::new(rom);
-        cpu.simulate_bootrom();
-
-        println!("Starting");
-
-        loop {
-            cpu.tick();
-        }
-    }
-}
@@ -6,8 +6,9 @@ template_header = """\
 /// This is a generated file. Do not modify.
 /// See `opcodes.py` to understand how this file was generated.
 use crate::cpu::{Cpu, R8, R16, Addr, Flag};
+use crate::memory::bus::Bus;
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 """
 template_footer = "}\n"
 
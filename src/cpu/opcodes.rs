@@ -1,8 +1,9 @@
 /// This is a generated file. Do not modify.
 /// See `opcodes.py` to understand how this file was generated.
 use crate::cpu::{Addr, Cpu, Flag, R16, R8};
+use crate::memory::bus::Bus;
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 	pub fn decode_exec(&mut self, opcode: u8) {
 		match opcode {
 			0x06 => {
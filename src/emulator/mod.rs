@@ -1,6 +1,11 @@
 use crate::apu::queue::BUFFER_SIZE;
-use crate::cpu::Cpu;
 use crate::events::Event;
+use crate::filter::VideoFilter;
+use crate::gameboy::{
+    ButtonCombo, ColorCorrection, DmgPreset, FrameBlending, GameBoy, Shades, TileMap, TilePalette,
+    SCREEN_HEIGHT, SCREEN_WIDTH, TILEMAP_SIZE, TILE_DATA_HEIGHT, TILE_DATA_WIDTH,
+};
+use crate::memory::monitor::Access;
 use wasm_bindgen::prelude::*;
 use web_sys::AudioContext;
 
@@ -9,37 +14,39 @@ const AUDIO_SAMPLE_RATE: f32 = 44100.0;
 const NUM_AUDIO_CHANNELS: u32 = 2;
 const SAMPLE_DURATION: f64 = BUFFER_SIZE as f64 / AUDIO_SAMPLE_RATE as f64;
 const LATENCY: f64 = 0.000;
+const PALETTES: usize = 19;
 
 #[wasm_bindgen]
 pub struct Emulator {
-    cpu: Cpu,
+    gb: GameBoy,
     ctx: AudioContext,
     next_start_time: Option<f64>,
     left_audio: Vec<f32>,
     right_audio: Vec<f32>,
+    filter: VideoFilter,
+    filtered: Vec<u8>,
 }
 
 #[wasm_bindgen]
 impl Emulator {
     pub fn new(data: Vec<u8>) -> Self {
-        let mut cpu = Cpu::new(data);
+        let gb = GameBoy::new(data);
 
         let ctx = AudioContext::new().unwrap();
 
-        cpu.simulate_bootrom();
-        // cpu.emulate_bootrom();
-
         Emulator {
-            cpu,
+            gb,
             ctx,
             next_start_time: None,
             left_audio: vec![0.0; BUFFER_SIZE],
             right_audio: vec![0.0; BUFFER_SIZE],
+            filter: VideoFilter::None,
+            filtered: Vec::new(),
         }
     }
 
     pub fn run_till_event(&mut self, max_cycles: usize) -> f64 {
-        match self.cpu.run_till_event(max_cycles) {
+        match self.gb.run_till_event(max_cycles) {
             Event::VBlank => 0.0,
             Event::AudioBufferFull(left, right) => {
                 for i in 0..BUFFER_SIZE {
@@ -62,14 +69,337 @@ impl Emulator {
     }
 
     pub fn screen(&self) -> *const u8 {
-        self.cpu.screen()
+        self.gb.framebuffer().as_ptr()
+    }
+
+    /// Picks the filter for `filtered_screen` by name, see `VideoFilter::from_name`.
+    /// Returns false if there's no such filter.
+    pub fn set_filter(&mut self, name: &str) -> bool {
+        match VideoFilter::from_name(name) {
+            Some(filter) => {
+                self.filter = filter;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The screen run through the filter, `filtered_width` by `filtered_height`.
+    pub fn filtered_screen(&mut self) -> *const u8 {
+        self.filter.apply(
+            self.gb.framebuffer(),
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            &mut self.filtered,
+        );
+        self.filtered.as_ptr()
+    }
+
+    pub fn filtered_width(&self) -> usize {
+        SCREEN_WIDTH * self.filter.scale()
+    }
+
+    pub fn filtered_height(&self) -> usize {
+        SCREEN_HEIGHT * self.filter.scale()
     }
 
     pub fn keyup(&mut self, key: usize) {
-        self.cpu.keyup(key);
+        self.gb.cpu_mut().keyup(key);
     }
 
     pub fn keydown(&mut self, key: usize) {
-        self.cpu.keydown(key);
+        self.gb.cpu_mut().keydown(key);
+    }
+
+    /// Names of the DMG palette presets, one per line, in `set_dmg_palette_preset`
+    /// order.
+    pub fn dmg_palette_presets() -> String {
+        DmgPreset::ALL
+            .iter()
+            .map(|preset| format!("{}\n", preset.name()))
+            .collect()
+    }
+
+    /// Returns false if there's no such preset.
+    pub fn set_dmg_palette_preset(&mut self, preset: usize) -> bool {
+        match DmgPreset::ALL.get(preset) {
+            Some(&preset) => {
+                self.gb.set_dmg_palette(preset.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Four RGB colours, lightest first, for the background (0), OBP0 (1) or
+    /// OBP1 (2). Returns false, changing nothing, for any other layer or if `rgb`
+    /// isn't 12 bytes.
+    pub fn set_dmg_palette_layer(&mut self, layer: usize, rgb: Vec<u8>) -> bool {
+        if layer > 2 || rgb.len() != 12 {
+            return false;
+        }
+
+        let mut shades: Shades = [(0, 0, 0); 4];
+        for (shade, c) in shades.iter_mut().zip(rgb.chunks(3)) {
+            *shade = (c[0], c[1], c[2]);
+        }
+
+        let mut palette = self.gb.dmg_palette();
+        match layer {
+            0 => palette.bg = shades,
+            1 => palette.obj0 = shades,
+            _ => palette.obj1 = shades,
+        }
+        self.gb.set_dmg_palette(palette);
+        true
+    }
+
+    /// Names of the CGB boot palettes, one per line, in `colorize` order.
+    pub fn button_combos() -> String {
+        ButtonCombo::ALL
+            .iter()
+            .map(|combo| format!("{}\n", combo.name()))
+            .collect()
+    }
+
+    /// Colours a DMG game like a CGB: 0 picks the palette from the game's title,
+    /// 1 to 12 the button combos from `button_combos`. Returns false for anything
+    /// else.
+    pub fn colorize(&mut self, combo: usize) -> bool {
+        let combo = match combo {
+            0 => None,
+            _ => match ButtonCombo::ALL.get(combo - 1) {
+                Some(&combo) => Some(combo),
+                None => return false,
+            },
+        };
+        self.gb.colorize(combo);
+        true
+    }
+
+    /// Turns colourising on, picking the palette from the game's title, or back
+    /// off to the DMG palette.
+    pub fn set_colorized(&mut self, colorized: bool) {
+        if colorized {
+            self.gb.colorize(None);
+        } else {
+            self.gb.set_dmg_palette(self.gb.dmg_palette());
+        }
+    }
+
+    /// CGB colour correction: none (0), correct curves (1), emulate hardware (2) or
+    /// preserve brightness (3). Returns false for any other mode.
+    pub fn set_color_correction(&mut self, mode: usize) -> bool {
+        match ColorCorrection::ALL.get(mode) {
+            Some(&mode) => {
+                self.gb.set_color_correction(mode);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Frame blending: off (0), 50% blend (1) or ghosting (2), where `response`
+    /// (0-1) is how far pixels move towards their new colour each frame. Returns
+    /// false for any other mode.
+    pub fn set_frame_blending(&mut self, mode: usize, response: f32) -> bool {
+        self.gb.set_frame_blending(match mode {
+            0 => FrameBlending::Off,
+            1 => FrameBlending::Blend,
+            2 => FrameBlending::Ghosting { response },
+            _ => return false,
+        });
+        true
+    }
+
+    /// Both VRAM banks of tiles as an RGBA image, `tile_data_width` by
+    /// `tile_data_height`, with one of the palettes from `palettes`. Empty if
+    /// there's no such palette.
+    pub fn tile_data(&self, palette: usize) -> Vec<u8> {
+        tile_palette(palette)
+            .and_then(|palette| self.gb.tile_data(palette))
+            .unwrap_or_default()
+    }
+
+    pub fn tile_data_width() -> usize {
+        TILE_DATA_WIDTH
+    }
+
+    pub fn tile_data_height() -> usize {
+        TILE_DATA_HEIGHT
+    }
+
+    /// The tilemap at $9800 (0) or $9C00 (1) as a `tilemap_size` square RGBA image,
+    /// empty for any other map. `overlay` outlines the viewport and window.
+    pub fn tilemap(&self, map: usize, overlay: bool) -> Vec<u8> {
+        let map = match map {
+            0 => TileMap::Low,
+            1 => TileMap::High,
+            _ => return Vec::new(),
+        };
+        self.gb.tilemap(map, overlay)
+    }
+
+    pub fn tilemap_size() -> usize {
+        TILEMAP_SIZE
+    }
+
+    /// One line per OAM entry, see `OamEntry`.
+    pub fn oam_entries(&self) -> String {
+        self.gb
+            .oam_entries()
+            .iter()
+            .map(|entry| format!("{}\n", entry))
+            .collect()
+    }
+
+    /// Entry `index` as RGBA, 8 pixels wide and `obj_height` high. Empty if
+    /// `index` isn't 0-39.
+    pub fn oam_thumbnail(&self, index: usize) -> Vec<u8> {
+        self.gb.sprite_thumbnail(index).unwrap_or_default()
+    }
+
+    pub fn obj_height(&self) -> usize {
+        self.gb.obj_height()
+    }
+
+    /// Objects picked on each of the 144 lines, 10 at most.
+    pub fn sprites_per_line(&self) -> Vec<u8> {
+        self.gb.sprites_per_line()
+    }
+
+    /// Four RGB colours each for BGP (0), OBP0 (1), OBP1 (2), the CGB background
+    /// palettes (3-10) and the CGB object palettes (11-18).
+    pub fn palettes(&self) -> Vec<u8> {
+        (0..PALETTES)
+            .filter_map(tile_palette)
+            .filter_map(|palette| self.gb.palette(palette))
+            .flat_map(|shades| shades.to_vec())
+            .flat_map(|(r, g, b)| vec![r, g, b])
+            .collect()
+    }
+
+    /// Colour `value` (0-3) of `palette` (numbered as in `palettes`): a shade 0-3
+    /// for the DMG palettes, a BGR555 colour for the CGB ones. `None` if either is
+    /// out of range.
+    pub fn palette_entry(&self, palette: usize, value: usize) -> Option<u16> {
+        tile_palette(palette).and_then(|palette| self.gb.palette_entry(palette, value))
+    }
+
+    /// Returns false, changing nothing, if `palette` or `value` is out of range.
+    pub fn set_palette_entry(&mut self, palette: usize, value: usize, entry: u16) -> bool {
+        match tile_palette(palette) {
+            Some(palette) => self.gb.set_palette_entry(palette, value, entry),
+            None => false,
+        }
+    }
+
+    /// Shows or hides the background (0), window (1) or objects (2). Returns false
+    /// for any other layer.
+    pub fn set_layer_visible(&mut self, layer: usize, visible: bool) -> bool {
+        let mut hidden = self.gb.hidden_layers();
+        match layer {
+            0 => hidden.background = !visible,
+            1 => hidden.window = !visible,
+            2 => hidden.objects = !visible,
+            _ => return false,
+        }
+        self.gb.set_hidden_layers(hidden);
+        true
+    }
+
+    /// Shows or hides the object in OAM entry `index`. Returns false if `index`
+    /// isn't 0-39.
+    pub fn set_oam_entry_visible(&mut self, index: usize, visible: bool) -> bool {
+        if index >= 40 {
+            return false;
+        }
+
+        let mut hidden = self.gb.hidden_layers();
+        if visible {
+            hidden.oam_entries &= !(1 << index);
+        } else {
+            hidden.oam_entries |= 1 << index;
+        }
+        self.gb.set_hidden_layers(hidden);
+        true
+    }
+
+    /// Faster on slow devices, see `cpu/cache.rs`.
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        if enabled {
+            self.gb.cpu_mut().enable_block_cache();
+        } else {
+            self.gb.cpu_mut().disable_block_cache();
+        }
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.gb.cpu_mut().enable_profiler();
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.gb.cpu_mut().disable_profiler();
+    }
+
+    pub fn profiler_report(&self, limit: usize) -> String {
+        match self.gb.cpu().profiler() {
+            Some(profiler) => profiler.report(limit),
+            None => String::new(),
+        }
+    }
+
+    pub fn profiler_folded_stacks(&self) -> String {
+        match self.gb.cpu().profiler() {
+            Some(profiler) => profiler.folded_stacks(),
+            None => String::new(),
+        }
+    }
+
+    pub fn enable_access_monitor(&mut self) {
+        self.gb.cpu_mut().mmu.enable_monitor();
+    }
+
+    pub fn disable_access_monitor(&mut self) {
+        self.gb.cpu_mut().mmu.disable_monitor();
+    }
+
+    /// 64K heatmap of reads (0), writes (1) or executed opcodes (2). Empty for any
+    /// other `kind`.
+    pub fn access_heatmap(&self, kind: usize) -> Vec<u8> {
+        let access = match kind {
+            0 => Access::Read,
+            1 => Access::Write,
+            2 => Access::Execute,
+            _ => return Vec::new(),
+        };
+
+        match self.gb.cpu().mmu.monitor() {
+            Some(monitor) => monitor.heatmap(access),
+            None => vec![0; 0x10000],
+        }
+    }
+
+    pub fn access_violations(&self) -> String {
+        match self.gb.cpu().mmu.monitor() {
+            Some(monitor) => monitor
+                .violations()
+                .iter()
+                .map(|violation| format!("{}\n", violation))
+                .collect(),
+            None => String::new(),
+        }
+    }
+}
+
+// BGP, OBP0, OBP1, then eight CGB palettes each for the background and objects.
+fn tile_palette(palette: usize) -> Option<TilePalette> {
+    match palette {
+        0 => Some(TilePalette::Bgp),
+        1 => Some(TilePalette::Obp0),
+        2 => Some(TilePalette::Obp1),
+        3..=10 => Some(TilePalette::CgbBg(palette - 3)),
+        11..=18 => Some(TilePalette::CgbObj(palette - 11)),
+        _ => None,
     }
 }
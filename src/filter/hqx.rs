@@ -0,0 +1,374 @@
+// hq2x and hq4x by Maxim Stepin. Each of a pixel's eight neighbours is compared
+// with it in YUV, and the ones that differ make an 8 bit pattern that picks how
+// the output pixels mix the centre with its neighbours. The original has a table
+// of all 256 patterns for each output pixel; like FFmpeg's vf_hqx this keeps the
+// rules behind the table for the top left corner only, and looks at the other
+// corners through mirrored neighbourhoods.
+//
+// References: https://en.wikipedia.org/wiki/Hqx
+//             https://github.com/FFmpeg/FFmpeg/blob/master/libavfilter/vf_hqx.c
+
+use super::{Block, Image, Pixel};
+
+// How far apart two colours' Y, U and V can be and still count as the same.
+const Y_THRESHOLD: i32 = 48;
+const U_THRESHOLD: i32 = 7;
+const V_THRESHOLD: i32 = 6;
+
+// The corners of a pixel, as the direction to go from it in x and y.
+const CORNERS: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+// The patterns for a pixel that's part of a diagonal line.
+const DIAGONAL: [(u8, u8); 13] = [
+    (0x6f, 0x2a),
+    (0x5b, 0x0a),
+    (0xbf, 0x3a),
+    (0xdf, 0x5a),
+    (0x9f, 0x8a),
+    (0xcf, 0x8a),
+    (0xef, 0x4e),
+    (0x3f, 0x0e),
+    (0xfb, 0x5a),
+    (0xbb, 0x8a),
+    (0x7f, 0x5a),
+    (0xaf, 0x8a),
+    (0xeb, 0x8a),
+];
+
+pub(super) fn hq2x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    for &(sx, sy) in CORNERS.iter() {
+        let corner = Corner::new(image, x, y, sx, sy);
+        block.set(
+            if sx < 0 { 0 } else { 1 },
+            if sy < 0 { 0 } else { 1 },
+            corner.hq2x(),
+        );
+    }
+}
+
+pub(super) fn hq4x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    for &(sx, sy) in CORNERS.iter() {
+        let corner = Corner::new(image, x, y, sx, sy);
+        for (n, &pixel) in corner.hq4x().iter().enumerate() {
+            let (i, j) = (n % 2, n / 2);
+            block.set(
+                if sx < 0 { i } else { 3 - i },
+                if sy < 0 { j } else { 3 - j },
+                pixel,
+            );
+        }
+    }
+}
+
+// A pixel's neighbourhood mirrored so that the corner being worked out is the top
+// left one:
+//
+//   w0 w1 w2
+//   w3 w4 w5
+//   w6 w7 w8
+//
+// with w4 the pixel itself. Bits 0-7 of `pattern` are set for w0-w3 and w5-w8
+// when they differ from it.
+struct Corner {
+    w: [Pixel; 9],
+    pattern: u8,
+}
+
+impl Corner {
+    fn new(image: &Image, x: usize, y: usize, sx: isize, sy: isize) -> Self {
+        let mut w = [[0; 4]; 9];
+        for (n, pixel) in w.iter_mut().enumerate() {
+            let dx = (n % 3) as isize - 1;
+            let dy = (n / 3) as isize - 1;
+            *pixel = image.get(x as isize + dx * -sx, y as isize + dy * -sy);
+        }
+
+        let mut pattern = 0;
+        for (bit, &n) in [0, 1, 2, 3, 5, 6, 7, 8].iter().enumerate() {
+            if w[n] != w[4] && differ(w[n], w[4]) {
+                pattern |= 1 << bit;
+            }
+        }
+
+        Self { w, pattern }
+    }
+
+    // Whether any of `patterns` matches, as (which neighbours to look at, which
+    // of those should differ).
+    fn matches(&self, patterns: &[(u8, u8)]) -> bool {
+        patterns
+            .iter()
+            .any(|&(mask, bits)| self.pattern & mask == bits)
+    }
+
+    fn differ(&self, a: usize, b: usize) -> bool {
+        differ(self.w[a], self.w[b])
+    }
+
+    // The top left output pixel.
+    fn hq2x(&self) -> Pixel {
+        let [w0, w1, _, w3, w4, ..] = self.w;
+        let is = |patterns: &[(u8, u8)]| self.matches(patterns);
+
+        if is(&[(0xbf, 0x37), (0xdb, 0x13)]) && self.differ(1, 5) {
+            interpolate(&[(w4, 3), (w3, 1)])
+        } else if is(&[(0xdb, 0x49), (0xef, 0x6d)]) && self.differ(7, 3) {
+            interpolate(&[(w4, 3), (w1, 1)])
+        } else if is(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && self.differ(3, 1) {
+            w4
+        } else if is(&DIAGONAL) && self.differ(3, 1) {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if is(&[(0x0b, 0x08)]) {
+            interpolate(&[(w4, 2), (w0, 1), (w1, 1)])
+        } else if is(&[(0x0b, 0x02)]) {
+            interpolate(&[(w4, 2), (w0, 1), (w3, 1)])
+        } else if is(&[(0x2f, 0x2f)]) {
+            interpolate(&[(w4, 14), (w3, 1), (w1, 1)])
+        } else if is(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+            interpolate(&[(w4, 5), (w1, 2), (w3, 1)])
+        } else if is(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+            interpolate(&[(w4, 5), (w3, 2), (w1, 1)])
+        } else if is(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+            interpolate(&[(w4, 3), (w3, 1)])
+        } else if is(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+            interpolate(&[(w4, 3), (w1, 1)])
+        } else if is(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+            interpolate(&[(w4, 2), (w3, 3), (w1, 3)])
+        } else if is(&[
+            (0xfb, 0x6a),
+            (0x6f, 0x6e),
+            (0x3f, 0x3e),
+            (0xfb, 0xfa),
+            (0xdf, 0xde),
+            (0xdf, 0x1e),
+        ]) {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if is(&[
+            (0x0a, 0x00),
+            (0x4f, 0x4b),
+            (0x9f, 0x1b),
+            (0x2f, 0x0b),
+            (0xbe, 0x0a),
+            (0xee, 0x0a),
+            (0x7e, 0x0a),
+            (0xeb, 0x4b),
+            (0x3b, 0x1b),
+        ]) {
+            interpolate(&[(w4, 2), (w3, 1), (w1, 1)])
+        } else {
+            interpolate(&[(w4, 6), (w3, 1), (w1, 1)])
+        }
+    }
+
+    // The top left 2x2 output pixels, row by row.
+    fn hq4x(&self) -> [Pixel; 4] {
+        let [w0, w1, _, w3, w4, ..] = self.w;
+        let is = |patterns: &[(u8, u8)]| self.matches(patterns);
+
+        let cond00 = is(&[(0xbf, 0x37), (0xdb, 0x13)]) && self.differ(1, 5);
+        let cond01 = is(&[(0xdb, 0x49), (0xef, 0x6d)]) && self.differ(7, 3);
+        let cond02 = is(&DIAGONAL) && self.differ(3, 1);
+        let cond03 = is(&[(0xdb, 0x49), (0xef, 0x6d)]);
+        let cond04 = is(&[(0xbf, 0x37), (0xdb, 0x13)]);
+        let cond05 = is(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]);
+        let cond06 = is(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]);
+        let cond07 = is(&[
+            (0x0b, 0x08),
+            (0xf9, 0x68),
+            (0xf3, 0x62),
+            (0x6d, 0x6c),
+            (0x67, 0x66),
+            (0x3d, 0x3c),
+            (0x37, 0x36),
+            (0xf9, 0xf8),
+            (0xdd, 0xdc),
+            (0xf3, 0xf2),
+            (0xd7, 0xd6),
+            (0xdd, 0x1c),
+            (0xd7, 0x16),
+            (0x0b, 0x02),
+        ]);
+        let cond08 =
+            is(&[(0x0f, 0x0b), (0x2b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && self.differ(3, 1);
+        let cond09 = is(&[(0x2f, 0x2f)]);
+        let cond10 = is(&[(0x0a, 0x00)]);
+        let cond11 = is(&[(0x0b, 0x09)]);
+        let cond12 = is(&[(0x7e, 0x2a), (0xef, 0xab)]);
+        let cond13 = is(&[(0xbf, 0x8f), (0x7e, 0x0e)]);
+        let cond14 = is(&[
+            (0x4f, 0x4b),
+            (0x9f, 0x1b),
+            (0x2f, 0x0b),
+            (0xbe, 0x0a),
+            (0xee, 0x0a),
+            (0x7e, 0x0a),
+            (0xeb, 0x4b),
+            (0x3b, 0x1b),
+        ]);
+        let cond15 = is(&[(0x0b, 0x03)]);
+
+        let top_left = if cond00 {
+            interpolate(&[(w4, 5), (w3, 3)])
+        } else if cond01 {
+            interpolate(&[(w4, 5), (w1, 3)])
+        } else if is(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && self.differ(3, 1) {
+            w4
+        } else if cond02 {
+            interpolate(&[(w4, 5), (w0, 3)])
+        } else if cond03 {
+            interpolate(&[(w4, 3), (w3, 1)])
+        } else if cond04 {
+            interpolate(&[(w4, 3), (w1, 1)])
+        } else if cond05 {
+            interpolate(&[(w4, 5), (w3, 3)])
+        } else if cond06 {
+            interpolate(&[(w4, 5), (w1, 3)])
+        } else if is(&[
+            (0x0f, 0x0b),
+            (0x5e, 0x0a),
+            (0x2b, 0x0b),
+            (0xbe, 0x0a),
+            (0x7a, 0x0a),
+            (0xee, 0x0a),
+        ]) {
+            interpolate(&[(w1, 1), (w3, 1)])
+        } else if cond07 {
+            interpolate(&[(w4, 5), (w0, 3)])
+        } else {
+            interpolate(&[(w4, 2), (w1, 1), (w3, 1)])
+        };
+
+        let top_right = if cond00 {
+            interpolate(&[(w4, 7), (w3, 1)])
+        } else if cond08 {
+            w4
+        } else if cond02 {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if cond09 {
+            w4
+        } else if cond10 {
+            interpolate(&[(w4, 5), (w1, 2), (w3, 1)])
+        } else if is(&[(0x0b, 0x08)]) {
+            interpolate(&[(w4, 5), (w1, 2), (w0, 1)])
+        } else if cond11 {
+            interpolate(&[(w4, 5), (w1, 3)])
+        } else if cond04 {
+            interpolate(&[(w1, 3), (w4, 1)])
+        } else if cond12 {
+            interpolate(&[(w1, 2), (w4, 1), (w3, 1)])
+        } else if cond13 {
+            interpolate(&[(w1, 5), (w3, 3)])
+        } else if cond05 {
+            interpolate(&[(w4, 7), (w3, 1)])
+        } else if is(&[
+            (0xf3, 0x62),
+            (0x67, 0x66),
+            (0x37, 0x36),
+            (0xf3, 0xf2),
+            (0xd7, 0xd6),
+            (0xd7, 0x16),
+            (0x0b, 0x02),
+        ]) {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if cond14 {
+            interpolate(&[(w1, 1), (w4, 1)])
+        } else {
+            interpolate(&[(w4, 3), (w1, 1)])
+        };
+
+        let bottom_left = if cond01 {
+            interpolate(&[(w4, 7), (w1, 1)])
+        } else if cond08 {
+            w4
+        } else if cond02 {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if cond09 {
+            w4
+        } else if cond10 {
+            interpolate(&[(w4, 5), (w3, 2), (w1, 1)])
+        } else if is(&[(0x0b, 0x02)]) {
+            interpolate(&[(w4, 5), (w3, 2), (w0, 1)])
+        } else if cond15 {
+            interpolate(&[(w4, 5), (w3, 3)])
+        } else if cond03 {
+            interpolate(&[(w3, 3), (w4, 1)])
+        } else if cond13 {
+            interpolate(&[(w3, 2), (w4, 1), (w1, 1)])
+        } else if cond12 {
+            interpolate(&[(w3, 5), (w1, 3)])
+        } else if cond06 {
+            interpolate(&[(w4, 7), (w1, 1)])
+        } else if is(&[
+            (0x0b, 0x08),
+            (0xf9, 0x68),
+            (0x6d, 0x6c),
+            (0x3d, 0x3c),
+            (0xf9, 0xf8),
+            (0xdd, 0xdc),
+            (0xdd, 0x1c),
+        ]) {
+            interpolate(&[(w4, 3), (w0, 1)])
+        } else if cond14 {
+            interpolate(&[(w3, 1), (w4, 1)])
+        } else {
+            interpolate(&[(w4, 3), (w3, 1)])
+        };
+
+        let bottom_right =
+            if is(&[(0x7f, 0x2b), (0xef, 0xab), (0xbf, 0x8f), (0x7f, 0x0f)]) && self.differ(3, 1) {
+                w4
+            } else if cond02 {
+                interpolate(&[(w4, 7), (w0, 1)])
+            } else if cond15 {
+                interpolate(&[(w4, 7), (w3, 1)])
+            } else if cond11 {
+                interpolate(&[(w4, 7), (w1, 1)])
+            } else if is(&[
+                (0x0a, 0x00),
+                (0x7e, 0x2a),
+                (0xef, 0xab),
+                (0xbf, 0x8f),
+                (0x7e, 0x0e),
+            ]) {
+                interpolate(&[(w4, 6), (w3, 1), (w1, 1)])
+            } else if cond07 {
+                interpolate(&[(w4, 7), (w0, 1)])
+            } else {
+                w4
+            };
+
+        [top_left, top_right, bottom_left, bottom_right]
+    }
+}
+
+// Weighted average, rounded down like hqx does. The weights add up to a power of
+// two.
+fn interpolate(pixels: &[(Pixel, u32)]) -> Pixel {
+    let total: u32 = pixels.iter().map(|&(_, weight)| weight).sum();
+    let mut out = [0; 4];
+
+    for c in 0..4 {
+        let sum: u32 = pixels
+            .iter()
+            .map(|&(pixel, weight)| pixel[c] as u32 * weight)
+            .sum();
+        out[c] = (sum / total) as u8;
+    }
+
+    out
+}
+
+fn yuv(pixel: Pixel) -> (i32, i32, i32) {
+    let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+    (
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000,
+        (500 * r - 419 * g - 81 * b) / 1000,
+    )
+}
+
+fn differ(a: Pixel, b: Pixel) -> bool {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (ya - yb).abs() > Y_THRESHOLD || (ua - ub).abs() > U_THRESHOLD || (va - vb).abs() > V_THRESHOLD
+}
@@ -0,0 +1,434 @@
+// Upscalers for the RGBA screen, for front ends that can't do it with shaders.
+//
+//  - `Nearest(n)` repeats every pixel n x n times.
+//  - `Scale2x`/`Scale3x` (EPX / AdvMAME) copy a neighbour's colour into the corners
+//    where two neighbours agree, which rounds off staircases without new colours.
+//  - `Hq2x`/`Hq4x` are Maxim Stepin's hqx, see hqx.rs.
+//  - `Xbrz(n)` is Zenju's xBRZ, see xbrz.rs.
+//  - `LcdGrid(n)` draws every pixel as an n x n dot with a dark gap below and to
+//    the right of it, like the DMG's dot matrix.
+//
+// References: https://www.scale2x.it/algorithm
+
+mod hqx;
+mod xbrz;
+
+type Pixel = [u8; 4];
+
+// How much of its colour an `LcdGrid` gap keeps, out of 256.
+const GRID_BRIGHTNESS: u32 = 160;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum VideoFilter {
+    #[default]
+    None,
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Hq4x,
+    Xbrz(usize),
+    LcdGrid(usize),
+}
+
+impl VideoFilter {
+    /// Parses names like "none", "scale2x", "hq4x", or "nearest", "xbrz" and
+    /// "lcd" followed by a scale (2-6).
+    pub fn from_name(name: &str) -> Option<VideoFilter> {
+        let filter = match name {
+            "none" => VideoFilter::None,
+            "scale2x" => VideoFilter::Scale2x,
+            "scale3x" => VideoFilter::Scale3x,
+            "hq2x" => VideoFilter::Hq2x,
+            "hq4x" => VideoFilter::Hq4x,
+            _ => {
+                let split = name.find(|c: char| c.is_ascii_digit())?;
+                let scale: usize = name[split..].parse().ok()?;
+                if !(2..=6).contains(&scale) {
+                    return None;
+                }
+
+                match &name[..split] {
+                    "nearest" => VideoFilter::Nearest(scale),
+                    "xbrz" => VideoFilter::Xbrz(scale),
+                    "lcd" => VideoFilter::LcdGrid(scale),
+                    _ => return None,
+                }
+            }
+        };
+
+        Some(filter)
+    }
+
+    /// How many times larger the output is in each direction.
+    pub fn scale(&self) -> usize {
+        match *self {
+            VideoFilter::None => 1,
+            VideoFilter::Scale2x | VideoFilter::Hq2x => 2,
+            VideoFilter::Scale3x => 3,
+            VideoFilter::Hq4x => 4,
+            VideoFilter::Nearest(n) | VideoFilter::Xbrz(n) | VideoFilter::LcdGrid(n) => n,
+        }
+    }
+
+    /// Scales an RGBA image into `dst`, resizing it as needed.
+    pub fn apply(&self, src: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+        let scale = self.scale();
+        dst.resize(width * scale * height * scale * 4, 0);
+
+        let image = Image { src, width, height };
+        // xBRZ decides how to blend every pixel's corners up front.
+        let corners = match *self {
+            VideoFilter::Xbrz(_) => xbrz::corners(&image),
+            _ => Vec::new(),
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut block = Block {
+                    dst: &mut dst[..],
+                    x: x * scale,
+                    y: y * scale,
+                    stride: width * scale,
+                };
+
+                match *self {
+                    VideoFilter::None | VideoFilter::Nearest(_) => {
+                        block.fill(scale, image.get(x as isize, y as isize))
+                    }
+                    VideoFilter::Scale2x => scale2x(&image, x, y, &mut block),
+                    VideoFilter::Scale3x => scale3x(&image, x, y, &mut block),
+                    VideoFilter::Hq2x => hqx::hq2x(&image, x, y, &mut block),
+                    VideoFilter::Hq4x => hqx::hq4x(&image, x, y, &mut block),
+                    VideoFilter::Xbrz(_) => xbrz::xbrz(&image, &corners, x, y, scale, &mut block),
+                    VideoFilter::LcdGrid(_) => lcd_grid(&image, x, y, scale, &mut block),
+                }
+            }
+        }
+    }
+}
+
+struct Image<'a> {
+    src: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Image<'a> {
+    // Clamped to the edges.
+    fn get(&self, x: isize, y: isize) -> Pixel {
+        let x = x.max(0).min(self.width as isize - 1) as usize;
+        let y = y.max(0).min(self.height as isize - 1) as usize;
+        let i = (y * self.width + x) * 4;
+        [
+            self.src[i],
+            self.src[i + 1],
+            self.src[i + 2],
+            self.src[i + 3],
+        ]
+    }
+}
+
+// The output pixels for one source pixel.
+struct Block<'a> {
+    dst: &'a mut [u8],
+    x: usize,
+    y: usize,
+    stride: usize,
+}
+
+impl<'a> Block<'a> {
+    fn get(&self, i: usize, j: usize) -> Pixel {
+        let at = ((self.y + j) * self.stride + self.x + i) * 4;
+        [
+            self.dst[at],
+            self.dst[at + 1],
+            self.dst[at + 2],
+            self.dst[at + 3],
+        ]
+    }
+
+    fn set(&mut self, i: usize, j: usize, pixel: Pixel) {
+        let at = ((self.y + j) * self.stride + self.x + i) * 4;
+        self.dst[at..at + 4].copy_from_slice(&pixel);
+    }
+
+    fn fill(&mut self, scale: usize, pixel: Pixel) {
+        for j in 0..scale {
+            for i in 0..scale {
+                self.set(i, j, pixel);
+            }
+        }
+    }
+}
+
+fn scale2x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let (x, y) = (x as isize, y as isize);
+    let b = image.get(x, y - 1);
+    let d = image.get(x - 1, y);
+    let e = image.get(x, y);
+    let f = image.get(x + 1, y);
+    let h = image.get(x, y + 1);
+
+    if b != h && d != f {
+        block.set(0, 0, if d == b { d } else { e });
+        block.set(1, 0, if b == f { f } else { e });
+        block.set(0, 1, if d == h { d } else { e });
+        block.set(1, 1, if h == f { f } else { e });
+    } else {
+        block.fill(2, e);
+    }
+}
+
+fn scale3x(image: &Image, x: usize, y: usize, block: &mut Block) {
+    let (x, y) = (x as isize, y as isize);
+    let a = image.get(x - 1, y - 1);
+    let b = image.get(x, y - 1);
+    let c = image.get(x + 1, y - 1);
+    let d = image.get(x - 1, y);
+    let e = image.get(x, y);
+    let f = image.get(x + 1, y);
+    let g = image.get(x - 1, y + 1);
+    let h = image.get(x, y + 1);
+    let i = image.get(x + 1, y + 1);
+
+    block.fill(3, e);
+    if b == h || d == f {
+        return;
+    }
+
+    let pick = |cond: bool, pixel: Pixel| if cond { pixel } else { e };
+    block.set(0, 0, pick(d == b, d));
+    block.set(1, 0, pick((d == b && e != c) || (b == f && e != a), b));
+    block.set(2, 0, pick(b == f, f));
+    block.set(0, 1, pick((d == b && e != g) || (d == h && e != a), d));
+    block.set(2, 1, pick((b == f && e != i) || (h == f && e != c), f));
+    block.set(0, 2, pick(d == h, d));
+    block.set(1, 2, pick((d == h && e != i) || (h == f && e != g), h));
+    block.set(2, 2, pick(h == f, f));
+}
+
+fn lcd_grid(image: &Image, x: usize, y: usize, scale: usize, block: &mut Block) {
+    let pixel = image.get(x as isize, y as isize);
+    let gap = mix(&[
+        (pixel, GRID_BRIGHTNESS),
+        ([0, 0, 0, pixel[3]], 256 - GRID_BRIGHTNESS),
+    ]);
+
+    for j in 0..scale {
+        for i in 0..scale {
+            let on_gap = i == scale - 1 || j == scale - 1;
+            block.set(i, j, if on_gap { gap } else { pixel });
+        }
+    }
+}
+
+// Weighted average.
+fn mix(pixels: &[(Pixel, u32)]) -> Pixel {
+    let total: u32 = pixels.iter().map(|&(_, weight)| weight).sum();
+    let mut out = [0; 4];
+
+    for c in 0..4 {
+        let sum: u32 = pixels
+            .iter()
+            .map(|&(pixel, weight)| pixel[c] as u32 * weight)
+            .sum();
+        out[c] = ((sum + total / 2) / total) as u8;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Pixel = [255, 255, 255, 255];
+    const K: Pixel = [0, 0, 0, 255];
+
+    fn image(pixels: &[Pixel]) -> Vec<u8> {
+        pixels
+            .iter()
+            .flat_map(|pixel| pixel.iter().cloned())
+            .collect()
+    }
+
+    fn pixel(dst: &[u8], width: usize, x: usize, y: usize) -> Pixel {
+        let i = (y * width + x) * 4;
+        [dst[i], dst[i + 1], dst[i + 2], dst[i + 3]]
+    }
+
+    // A black staircase on white:
+    //   K W W
+    //   K K W
+    //   K K K
+    fn staircase() -> Vec<u8> {
+        image(&[K, W, W, K, K, W, K, K, K])
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(VideoFilter::from_name("hq4x"), Some(VideoFilter::Hq4x));
+        assert_eq!(VideoFilter::from_name("xbrz3"), Some(VideoFilter::Xbrz(3)));
+        assert_eq!(VideoFilter::from_name("hq3x"), None);
+        assert_eq!(VideoFilter::from_name("xbrz7"), None);
+        assert_eq!(
+            VideoFilter::from_name("lcd4"),
+            Some(VideoFilter::LcdGrid(4))
+        );
+        assert_eq!(VideoFilter::from_name("nearest9"), None);
+        assert_eq!(VideoFilter::from_name("blur2"), None);
+    }
+
+    #[test]
+    fn test_flat_colour_stays_flat() {
+        let src = image(&[[10, 20, 30, 255]; 9]);
+
+        for &filter in [
+            VideoFilter::None,
+            VideoFilter::Nearest(3),
+            VideoFilter::Scale2x,
+            VideoFilter::Scale3x,
+            VideoFilter::Hq2x,
+            VideoFilter::Hq4x,
+            VideoFilter::Xbrz(2),
+            VideoFilter::Xbrz(5),
+        ]
+        .iter()
+        {
+            let mut dst = Vec::new();
+            filter.apply(&src, 3, 3, &mut dst);
+
+            let scale = filter.scale();
+            assert_eq!(dst.len(), 9 * scale * scale * 4);
+            assert!(
+                dst.chunks(4).all(|p| p == [10, 20, 30, 255]),
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn test_scale2x_rounds_corners() {
+        let mut dst = Vec::new();
+        VideoFilter::Scale2x.apply(&staircase(), 3, 3, &mut dst);
+
+        // The white pixel in the middle of the top row gets a black corner where
+        // the step is.
+        assert_eq!(pixel(&dst, 6, 2, 1), K);
+        assert_eq!(pixel(&dst, 6, 3, 1), W);
+        assert_eq!(pixel(&dst, 6, 2, 0), W);
+    }
+
+    #[test]
+    fn test_edge_filters_smooth_diagonals() {
+        for &filter in [VideoFilter::Hq4x, VideoFilter::Xbrz(4)].iter() {
+            let mut dst = Vec::new();
+            filter.apply(&staircase(), 3, 3, &mut dst);
+
+            // Somewhere on the step there are greys.
+            let grey = dst.chunks(4).any(|p| p[0] > 0 && p[0] < 255);
+            assert!(grey, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_hqx_lone_pixel() {
+        let mut src = [W; 9];
+        src[4] = K;
+
+        // Every corner is (14 * centre + 2 neighbours) / 16.
+        let mut dst = Vec::new();
+        VideoFilter::Hq2x.apply(&image(&src), 3, 3, &mut dst);
+        for &(x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)].iter() {
+            assert_eq!(pixel(&dst, 6, x, y), [31, 31, 31, 255]);
+        }
+        assert_eq!(pixel(&dst, 6, 1, 1), W);
+
+        // Only the outer corners are blended, half with the neighbours.
+        VideoFilter::Hq4x.apply(&image(&src), 3, 3, &mut dst);
+        for &(x, y) in [(4, 4), (7, 4), (4, 7), (7, 7)].iter() {
+            assert_eq!(pixel(&dst, 12, x, y), [127, 127, 127, 255]);
+        }
+        assert_eq!(pixel(&dst, 12, 5, 4), K);
+        assert_eq!(pixel(&dst, 12, 5, 5), K);
+        assert_eq!(pixel(&dst, 12, 3, 3), W);
+    }
+
+    #[test]
+    fn test_xbrz_lone_pixel() {
+        let mut src = [W; 9];
+        src[4] = K;
+
+        // The pixel clashes with itself at every corner, so they're only rounded off.
+        let mut dst = Vec::new();
+        VideoFilter::Xbrz(2).apply(&image(&src), 3, 3, &mut dst);
+        for &(x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)].iter() {
+            assert_eq!(pixel(&dst, 6, x, y), [53, 53, 53, 255]);
+        }
+        assert_eq!(pixel(&dst, 6, 1, 2), W);
+
+        VideoFilter::Xbrz(4).apply(&image(&src), 3, 3, &mut dst);
+        assert_eq!(pixel(&dst, 12, 4, 4), [173, 173, 173, 255]);
+        assert_eq!(pixel(&dst, 12, 7, 7), [173, 173, 173, 255]);
+        assert_eq!(pixel(&dst, 12, 5, 4), [22, 22, 22, 255]);
+        assert_eq!(pixel(&dst, 12, 7, 6), [22, 22, 22, 255]);
+        assert_eq!(pixel(&dst, 12, 6, 4), [22, 22, 22, 255]);
+        assert_eq!(pixel(&dst, 12, 5, 5), K);
+        assert_eq!(pixel(&dst, 12, 6, 6), K);
+    }
+
+    #[test]
+    fn test_shallow_line() {
+        // A black slope going one down every two across:
+        //   W W W W W W
+        //   K K W W W W
+        //   K K K K W W
+        //   K K K K K K
+        let rows = ["WWWWWW", "KKWWWW", "KKKKWW", "KKKKKK"];
+        let src: Vec<Pixel> = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| if c == 'K' { K } else { W }))
+            .collect();
+
+        // Both anti-alias every step with a quarter and three quarters of white.
+        for &filter in [VideoFilter::Hq2x, VideoFilter::Xbrz(2)].iter() {
+            let mut dst = Vec::new();
+            filter.apply(&image(&src), 6, 4, &mut dst);
+
+            let row = |y: usize| -> Vec<u8> { (0..12).map(|x| pixel(&dst, 12, x, y)[0]).collect() };
+            assert_eq!(row(1), [255; 12], "{:?}", filter);
+            assert_eq!(
+                row(2),
+                [0, 0, 63, 191, 255, 255, 255, 255, 255, 255, 255, 255],
+                "{:?}",
+                filter
+            );
+            assert_eq!(
+                row(3),
+                [0, 0, 0, 0, 63, 191, 255, 255, 255, 255, 255, 255],
+                "{:?}",
+                filter
+            );
+            assert_eq!(
+                row(4),
+                [0, 0, 0, 0, 0, 0, 63, 191, 255, 255, 255, 255],
+                "{:?}",
+                filter
+            );
+            assert_eq!(row(6), [0; 12], "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_lcd_grid() {
+        let mut dst = Vec::new();
+        VideoFilter::LcdGrid(3).apply(&image(&[W]), 1, 1, &mut dst);
+
+        assert_eq!(pixel(&dst, 3, 0, 0), W);
+        assert_eq!(pixel(&dst, 3, 1, 1), W);
+        assert_eq!(pixel(&dst, 3, 2, 0), [159, 159, 159, 255]);
+        assert_eq!(pixel(&dst, 3, 0, 2), [159, 159, 159, 255]);
+    }
+}
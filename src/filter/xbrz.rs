@@ -0,0 +1,397 @@
+// xBRZ by Zenju. A first pass goes over every 2x2 square of the image and decides
+// whether one of its diagonals is an edge, by weighing the colour differences
+// across each diagonal against those along it, and which of the four pixels sit
+// on the edge's outside. The second pass then blends every such pixel's corner
+// towards its neighbours, one corner at a time: along a shallow, steep or 45
+// degree line depending on the colour differences around it, or just rounding
+// off the corner when the pixel is an isolated detail.
+//
+// References: https://sourceforge.net/projects/xbrz/
+//             https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+
+use super::{Block, Image, Pixel};
+
+// The defaults from xBRZ's ScalerCfg.
+const EQUAL_COLOR_TOLERANCE: f64 = 30.0;
+const CENTER_DIRECTION_BIAS: f64 = 4.0;
+const DOMINANT_DIRECTION_THRESHOLD: f64 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f64 = 2.2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Blend {
+    #[default]
+    None,
+    Normal,
+    Dominant,
+}
+
+// How to blend each corner of a pixel, clockwise from the top left.
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Corners([Blend; 4]);
+
+impl Corners {
+    const TOP_LEFT: usize = 0;
+    const TOP_RIGHT: usize = 1;
+    const BOTTOM_RIGHT: usize = 2;
+    const BOTTOM_LEFT: usize = 3;
+
+    // As seen after turning the pixel `rotation` quarter turns clockwise.
+    fn rotated(&self, rotation: usize) -> Self {
+        let mut out = [Blend::None; 4];
+        for (n, blend) in out.iter_mut().enumerate() {
+            *blend = self.0[(n + 4 - rotation) % 4];
+        }
+        Corners(out)
+    }
+}
+
+// The first pass: the corners of every pixel in the image, row by row.
+pub(super) fn corners(image: &Image) -> Vec<Corners> {
+    let (width, height) = (image.width, image.height);
+    let mut corners = vec![Corners::default(); width * height];
+
+    // Squares hanging off the edges only see clamped copies of the edge pixels,
+    // and never have an edge through them, so it's enough to look at the ones
+    // inside the image.
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let [f, g, j, k] = square(image, x, y);
+            let at = y * width + x;
+            corners[at].0[Corners::BOTTOM_RIGHT] = f;
+            corners[at + 1].0[Corners::BOTTOM_LEFT] = g;
+            corners[at + width].0[Corners::TOP_RIGHT] = j;
+            corners[at + width + 1].0[Corners::TOP_LEFT] = k;
+        }
+    }
+
+    corners
+}
+
+// How to blend the corners facing the middle of the square of pixels F, G, J and K,
+// in that order:
+//
+//      A  B  C  D
+//      E  F  G  H
+//      I  J  K  L
+//      M  N  O  P
+fn square(image: &Image, x: usize, y: usize) -> [Blend; 4] {
+    let n = |dx: isize, dy: isize| image.get(x as isize + dx, y as isize + dy);
+    let (b, c) = (n(0, -1), n(1, -1));
+    let (e, f, g, h) = (n(-1, 0), n(0, 0), n(1, 0), n(2, 0));
+    let (i, j, k, l) = (n(-1, 1), n(0, 1), n(1, 1), n(2, 1));
+    let (nn, o) = (n(0, 2), n(1, 2));
+
+    let mut blend = [Blend::None; 4];
+    if (f == g && j == k) || (f == j && g == k) {
+        return blend;
+    }
+
+    let jg = distance(i, f)
+        + distance(f, c)
+        + distance(nn, k)
+        + distance(k, h)
+        + CENTER_DIRECTION_BIAS * distance(j, g);
+    let fk = distance(e, j)
+        + distance(j, o)
+        + distance(b, g)
+        + distance(g, l)
+        + CENTER_DIRECTION_BIAS * distance(f, k);
+
+    if jg < fk {
+        let level = if DOMINANT_DIRECTION_THRESHOLD * jg < fk {
+            Blend::Dominant
+        } else {
+            Blend::Normal
+        };
+        if f != g && f != j {
+            blend[0] = level;
+        }
+        if k != j && k != g {
+            blend[3] = level;
+        }
+    } else if fk < jg {
+        let level = if DOMINANT_DIRECTION_THRESHOLD * fk < jg {
+            Blend::Dominant
+        } else {
+            Blend::Normal
+        };
+        if j != f && j != k {
+            blend[2] = level;
+        }
+        if g != f && g != k {
+            blend[1] = level;
+        }
+    }
+
+    blend
+}
+
+// The second pass for one pixel.
+pub(super) fn xbrz(
+    image: &Image,
+    corners: &[Corners],
+    x: usize,
+    y: usize,
+    scale: usize,
+    block: &mut Block,
+) {
+    block.fill(scale, image.get(x as isize, y as isize));
+
+    let corners = corners[y * image.width + x];
+    for rotation in 0..4 {
+        let mut out = Output {
+            block,
+            scale,
+            rotation,
+        };
+        blend_corner(image, corners.rotated(rotation), x, y, &mut out);
+    }
+}
+
+// Blends the bottom right corner of the pixel E, after turning it and its
+// neighbours `out.rotation` quarter turns clockwise:
+//
+//      A  B  C
+//      D  E  F
+//      G  H  I
+fn blend_corner(image: &Image, corners: Corners, x: usize, y: usize, out: &mut Output) {
+    let blend = corners.0[Corners::BOTTOM_RIGHT];
+    if blend == Blend::None {
+        return;
+    }
+
+    let n = |dx: isize, dy: isize| {
+        let (dx, dy) = out.unrotate(dx, dy);
+        image.get(x as isize + dx, y as isize + dy)
+    };
+    let (b, c) = (n(0, -1), n(1, -1));
+    let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+    let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+    let same = |a: Pixel, b: Pixel| distance(a, b) < EQUAL_COLOR_TOLERANCE;
+
+    // Blending along a line only makes sense when it doesn't clash with another
+    // corner, except for 90 degree corners, and the pixel isn't just a detail
+    // on a bigger area of the same colour.
+    let clashes = (corners.0[Corners::TOP_RIGHT] != Blend::None && !same(e, g))
+        || (corners.0[Corners::BOTTOM_LEFT] != Blend::None && !same(e, c));
+    let detail = !same(e, i) && same(g, h) && same(h, i) && same(i, f) && same(f, c);
+    let line = blend == Blend::Dominant || (!clashes && !detail);
+
+    let colour = if distance(e, f) <= distance(e, h) {
+        f
+    } else {
+        h
+    };
+
+    if !line {
+        out.apply(CORNER[out.scale - 2], colour, false);
+        return;
+    }
+
+    let fg = distance(f, g);
+    let hc = distance(h, c);
+    let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+    let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+
+    match (shallow, steep) {
+        (true, true) => out.apply(STEEP_AND_SHALLOW[out.scale - 2], colour, false),
+        (true, false) => out.apply(SHALLOW[out.scale - 2], colour, false),
+        (false, true) => out.apply(SHALLOW[out.scale - 2], colour, true),
+        (false, false) => out.apply(DIAGONAL[out.scale - 2], colour, false),
+    }
+}
+
+// A pixel's block of output pixels, turned like in `blend_corner`.
+struct Output<'a, 'b> {
+    block: &'a mut Block<'b>,
+    scale: usize,
+    rotation: usize,
+}
+
+impl<'a, 'b> Output<'a, 'b> {
+    // Where a neighbour at dx, dy after the rotation is in the image.
+    fn unrotate(&self, mut dx: isize, mut dy: isize) -> (isize, isize) {
+        for _ in 0..self.rotation {
+            let turned = (dy, -dx);
+            dx = turned.0;
+            dy = turned.1;
+        }
+        (dx, dy)
+    }
+
+    // Blends `colour` into the output pixels in `steps`, with the rows and
+    // columns swapped if `transpose` is set.
+    fn apply(&mut self, steps: &[Step], colour: Pixel, transpose: bool) {
+        for &(row, column, weight, total) in steps {
+            let (row, column) = if transpose {
+                (column, row)
+            } else {
+                (row, column)
+            };
+
+            let (mut i, mut j) = (column, row);
+            for _ in 0..self.rotation {
+                let turned = (j, self.scale - 1 - i);
+                i = turned.0;
+                j = turned.1;
+            }
+
+            let old = self.block.get(i, j);
+            let mut new = [0; 4];
+            for c in 0..4 {
+                new[c] =
+                    ((colour[c] as u32 * weight + old[c] as u32 * (total - weight)) / total) as u8;
+            }
+            self.block.set(i, j, new);
+        }
+    }
+}
+
+// An output pixel's row and column within the bottom right corner of the block,
+// and how much of the new colour it takes, as a fraction.
+type Step = (usize, usize, u32, u32);
+
+// For scales 2 to 6. A steep line is a shallow one with the rows and columns
+// swapped.
+const SHALLOW: [&[Step]; 5] = [
+    &[(1, 0, 1, 4), (1, 1, 3, 4)],
+    &[(2, 0, 1, 4), (1, 2, 1, 4), (2, 1, 3, 4), (2, 2, 1, 1)],
+    &[
+        (3, 0, 1, 4),
+        (2, 2, 1, 4),
+        (3, 1, 3, 4),
+        (2, 3, 3, 4),
+        (3, 2, 1, 1),
+        (3, 3, 1, 1),
+    ],
+    &[
+        (4, 0, 1, 4),
+        (3, 2, 1, 4),
+        (2, 4, 1, 4),
+        (4, 1, 3, 4),
+        (3, 3, 3, 4),
+        (4, 2, 1, 1),
+        (4, 3, 1, 1),
+        (4, 4, 1, 1),
+        (3, 4, 1, 1),
+    ],
+    &[
+        (5, 0, 1, 4),
+        (4, 2, 1, 4),
+        (3, 4, 1, 4),
+        (5, 1, 3, 4),
+        (4, 3, 3, 4),
+        (3, 5, 3, 4),
+        (5, 2, 1, 1),
+        (5, 3, 1, 1),
+        (5, 4, 1, 1),
+        (5, 5, 1, 1),
+        (4, 4, 1, 1),
+        (4, 5, 1, 1),
+    ],
+];
+
+const STEEP_AND_SHALLOW: [&[Step]; 5] = [
+    &[(1, 0, 1, 4), (0, 1, 1, 4), (1, 1, 5, 6)],
+    &[
+        (2, 0, 1, 4),
+        (0, 2, 1, 4),
+        (2, 1, 3, 4),
+        (1, 2, 3, 4),
+        (2, 2, 1, 1),
+    ],
+    &[
+        (3, 1, 3, 4),
+        (1, 3, 3, 4),
+        (3, 0, 1, 4),
+        (0, 3, 1, 4),
+        (2, 2, 1, 3),
+        (3, 3, 1, 1),
+        (3, 2, 1, 1),
+        (2, 3, 1, 1),
+    ],
+    &[
+        (0, 4, 1, 4),
+        (2, 3, 1, 4),
+        (1, 4, 3, 4),
+        (4, 0, 1, 4),
+        (3, 2, 1, 4),
+        (4, 1, 3, 4),
+        (3, 3, 2, 3),
+        (2, 4, 1, 1),
+        (3, 4, 1, 1),
+        (4, 4, 1, 1),
+        (4, 2, 1, 1),
+        (4, 3, 1, 1),
+    ],
+    &[
+        (0, 5, 1, 4),
+        (2, 4, 1, 4),
+        (1, 5, 3, 4),
+        (3, 4, 3, 4),
+        (5, 0, 1, 4),
+        (4, 2, 1, 4),
+        (5, 1, 3, 4),
+        (4, 3, 3, 4),
+        (2, 5, 1, 1),
+        (3, 5, 1, 1),
+        (4, 5, 1, 1),
+        (5, 5, 1, 1),
+        (4, 4, 1, 1),
+        (5, 4, 1, 1),
+        (5, 2, 1, 1),
+        (5, 3, 1, 1),
+    ],
+];
+
+const DIAGONAL: [&[Step]; 5] = [
+    &[(1, 1, 1, 2)],
+    &[(1, 2, 1, 8), (2, 1, 1, 8), (2, 2, 7, 8)],
+    &[(3, 2, 1, 2), (2, 3, 1, 2), (3, 3, 1, 1)],
+    &[
+        (4, 2, 1, 8),
+        (3, 3, 1, 8),
+        (2, 4, 1, 8),
+        (4, 3, 7, 8),
+        (3, 4, 7, 8),
+        (4, 4, 1, 1),
+    ],
+    &[
+        (5, 3, 1, 2),
+        (4, 4, 1, 2),
+        (3, 5, 1, 2),
+        (4, 5, 1, 1),
+        (5, 5, 1, 1),
+        (5, 4, 1, 1),
+    ],
+];
+
+const CORNER: [&[Step]; 5] = [
+    &[(1, 1, 21, 100)],
+    &[(2, 2, 45, 100)],
+    &[(3, 3, 68, 100), (3, 2, 9, 100), (2, 3, 9, 100)],
+    &[(4, 4, 86, 100), (4, 3, 23, 100), (3, 4, 23, 100)],
+    &[
+        (5, 5, 97, 100),
+        (4, 5, 42, 100),
+        (5, 4, 42, 100),
+        (5, 3, 6, 100),
+        (3, 5, 6, 100),
+    ],
+];
+
+// xBRZ's colour distance, in YCbCr with the BT.2020 weights.
+fn distance(a: Pixel, b: Pixel) -> f64 {
+    const K_B: f64 = 0.0593;
+    const K_R: f64 = 0.2627;
+    const K_G: f64 = 1.0 - K_B - K_R;
+
+    let dr = a[0] as f64 - b[0] as f64;
+    let dg = a[1] as f64 - b[1] as f64;
+    let db = a[2] as f64 - b[2] as f64;
+
+    let y = K_R * dr + K_G * dg + K_B * db;
+    let cb = 0.5 / (1.0 - K_B) * (db - y);
+    let cr = 0.5 / (1.0 - K_R) * (dr - y);
+    (y * y + cb * cb + cr * cr).sqrt()
+}
@@ -0,0 +1,315 @@
+// Two Game Boys joined by a link cable, run in lockstep. Each step advances
+// whichever of the two is behind by one instruction and then moves any serial clock
+// pulses across the cable, so neither ever gets further ahead than the longest
+// instruction (or DMA stall). Time is counted in single speed clocks so a CGB in
+// double speed can be linked to a DMG.
+//
+// `connect_infrared` also points their IR ports at each other, lockstep keeps the
+// LED and sensor in sync well enough for games timing pulses in software.
+//
+// `RemoteLinkedGameBoy` is one side of a link to another process, see serial/net.rs.
+
+use crate::cpu::CgbSpeed;
+use crate::gameboy::{GameBoy, FRAME_CYCLES};
+use crate::infrared::InfraredPeer;
+use crate::serial::link::LinkCable;
+use crate::serial::net::{LinkTransport, NetworkLink};
+use std::io;
+
+pub struct LinkedGameBoys {
+    pub gbs: [GameBoy; 2],
+    cable: LinkCable,
+    clocks: [usize; 2],
+}
+
+impl LinkedGameBoys {
+    pub fn new(mut first: GameBoy, mut second: GameBoy) -> Self {
+        let (cable, port0, port1) = LinkCable::new();
+        first.connect_serial(Box::new(port0));
+        second.connect_serial(Box::new(port1));
+
+        let mut linked = Self {
+            gbs: [first, second],
+            cable,
+            clocks: [0; 2],
+        };
+        linked.sync(0);
+        linked.sync(1);
+        linked
+    }
+
+    /// Points the two IR ports at each other.
+    pub fn connect_infrared(&mut self) {
+        let (first, second) = InfraredPeer::pair();
+        self.gbs[0].connect_infrared(Box::new(first));
+        self.gbs[1].connect_infrared(Box::new(second));
+    }
+
+    /// Runs one instruction on whichever side is behind.
+    pub fn step(&mut self) {
+        let side = if self.clocks[0] <= self.clocks[1] {
+            0
+        } else {
+            1
+        };
+
+        self.clocks[side] += step(&mut self.gbs[side]);
+
+        self.sync(side);
+        self.sync(1 - side);
+    }
+
+    /// Runs both for one frame's worth of clocks.
+    pub fn step_frame(&mut self) {
+        let end = self.clocks[0].min(self.clocks[1]) + FRAME_CYCLES;
+
+        while self.clocks[0] < end || self.clocks[1] < end {
+            self.step();
+        }
+    }
+
+    /// How far ahead one side is of the other, in clocks.
+    pub fn skew(&self) -> usize {
+        let [a, b] = self.clocks;
+        a.max(b) - a.min(b)
+    }
+
+    fn sync(&mut self, side: usize) {
+        self.cable
+            .sync(side, &mut self.gbs[side].cpu_mut().mmu.serial);
+    }
+}
+
+// One instruction, in single speed clocks.
+fn step(gb: &mut GameBoy) -> usize {
+    match gb.cpu().mmu.cgb_mode.speed {
+        CgbSpeed::Normal => gb.step(),
+        CgbSpeed::Double => gb.step() / 2,
+    }
+}
+
+pub struct RemoteLinkedGameBoy<T: LinkTransport> {
+    pub gb: GameBoy,
+    link: NetworkLink<T>,
+    clock: u64,
+}
+
+impl<T: LinkTransport> RemoteLinkedGameBoy<T> {
+    pub fn new(mut gb: GameBoy, transport: T) -> Self {
+        let (link, port) = NetworkLink::new(transport);
+        gb.connect_serial(Box::new(port));
+
+        Self { gb, link, clock: 0 }
+    }
+
+    pub fn link_mut(&mut self) -> &mut NetworkLink<T> {
+        &mut self.link
+    }
+
+    /// Single speed clocks since power on.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Runs one instruction, unless too far ahead of the other side. Returns false
+    /// if it had to wait, in which case the caller should give the other side time
+    /// to catch up before trying again.
+    pub fn step(&mut self) -> io::Result<bool> {
+        let ran = !self.link.must_wait(self.clock);
+        if ran {
+            self.clock += step(&mut self.gb) as u64;
+        }
+
+        self.link
+            .sync(&mut self.gb.cpu_mut().mmu.serial, self.clock)?;
+
+        Ok(ran)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::R8;
+    use crate::serial::net::{ChannelTransport, Message};
+
+    // Sends `value` with the given SC (internal or external clock), then stores
+    // each received byte at C000+ and sends it back plus one, forever.
+    fn link_rom(value: u8, sc: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x167].copy_from_slice(&[
+            0x21, 0x00, 0xC0, // ld hl,$C000
+            0x3E, value, // ld a,value
+            0xE0, 0x01, // send: ldh (SB),a
+            0x3E, sc, // ld a,sc
+            0xE0, 0x02, // ldh (SC),a
+            0xF0, 0x02, // wait: ldh a,(SC)
+            0xCB, 0x7F, // bit 7,a
+            0x20, 0xFA, // jr nz,wait
+            0xF0, 0x01, // ldh a,(SB)
+            0x22, // ld (hl+),a
+            0x3C, // inc a
+            0x18, 0xEE, // jr send
+        ]);
+        rom
+    }
+
+    fn run_linked() -> LinkedGameBoys {
+        let master = GameBoy::new(link_rom(0x10, 0x81));
+        let slave = GameBoy::new(link_rom(0x80, 0x80));
+        let mut linked = LinkedGameBoys::new(master, slave);
+
+        for _ in 0..2 {
+            linked.step_frame();
+            assert!(linked.skew() <= 24);
+        }
+        linked
+    }
+
+    #[test]
+    fn test_linked_transfer() {
+        let mut linked = run_linked();
+
+        // Each side receives what the other sent, which is one more than the byte it
+        // received before.
+        let master: Vec<u8> = (0..4)
+            .map(|i| linked.gbs[0].cpu_mut().mmu.get_byte(0xC000 + i))
+            .collect();
+        let slave: Vec<u8> = (0..4)
+            .map(|i| linked.gbs[1].cpu_mut().mmu.get_byte(0xC000 + i))
+            .collect();
+
+        assert_eq!(master, vec![0x80, 0x11, 0x82, 0x13]);
+        assert_eq!(slave, vec![0x10, 0x81, 0x12, 0x83]);
+
+        // Deterministic: a second run ends in exactly the same state.
+        let mut again = run_linked();
+        for side in 0..2 {
+            assert_eq!(
+                linked.gbs[side].cpu().get_r8(&R8::L),
+                again.gbs[side].cpu().get_r8(&R8::L)
+            );
+            assert_eq!(
+                linked.gbs[side].cpu_mut().mmu.get_byte(0xC020),
+                again.gbs[side].cpu_mut().mmu.get_byte(0xC020)
+            );
+        }
+    }
+
+    #[test]
+    fn test_linked_infrared() {
+        let mut sender = vec![0; 0x8000];
+        sender[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        sender[0x143] = 0x80;
+        sender[0x150..0x156].copy_from_slice(&[
+            0x3E, 0xC1, // ld a,$C1
+            0xE0, 0x56, // ldh (RP),a
+            0x18, 0xFE, // jr @
+        ]);
+
+        let mut receiver = sender.clone();
+        receiver[0x150..0x163].copy_from_slice(&[
+            0x3E, 0xC0, // ld a,$C0
+            0xE0, 0x56, // ldh (RP),a
+            0xF0, 0x56, // wait: ldh a,(RP)
+            0xCB, 0x4F, // bit 1,a
+            0x20, 0xFA, // jr nz,wait
+            0x3E, 0x42, // ld a,$42
+            0xEA, 0x00, 0xC0, // ld ($C000),a
+            0x18, 0xFE, // jr @
+            0x00, 0x00,
+        ]);
+
+        let mut linked = LinkedGameBoys::new(GameBoy::new(sender), GameBoy::new(receiver));
+        linked.step_frame();
+        assert_ne!(linked.gbs[1].cpu_mut().mmu.get_byte(0xC000), 0x42);
+
+        linked.connect_infrared();
+        linked.step_frame();
+        assert_eq!(linked.gbs[1].cpu_mut().mmu.get_byte(0xC000), 0x42);
+    }
+
+    #[test]
+    fn test_remote_link() {
+        let (transport0, transport1) = ChannelTransport::pair();
+        let mut sides = [
+            RemoteLinkedGameBoy::new(GameBoy::new(link_rom(0x10, 0x81)), transport0),
+            RemoteLinkedGameBoy::new(GameBoy::new(link_rom(0x80, 0x80)), transport1),
+        ];
+
+        // Each side runs on its own, only held back by `must_wait`. Alternating
+        // uneven slices keeps one ahead of the other most of the time.
+        while sides
+            .iter()
+            .any(|side| side.clock() < 2 * FRAME_CYCLES as u64)
+        {
+            for (i, side) in sides.iter_mut().enumerate() {
+                for _ in 0..50 + 30 * i {
+                    side.step().unwrap();
+                }
+            }
+        }
+
+        let master: Vec<u8> = (0..4)
+            .map(|i| sides[0].gb.cpu_mut().mmu.get_byte(0xC000 + i))
+            .collect();
+        let slave: Vec<u8> = (0..4)
+            .map(|i| sides[1].gb.cpu_mut().mmu.get_byte(0xC000 + i))
+            .collect();
+
+        assert_eq!(master, vec![0x80, 0x11, 0x82, 0x13]);
+        assert_eq!(slave, vec![0x10, 0x81, 0x12, 0x83]);
+    }
+
+    #[test]
+    fn test_remote_link_late_armed() {
+        let (transport, mut remote) = ChannelTransport::pair();
+        let mut master = RemoteLinkedGameBoy::new(GameBoy::new(link_rom(0x10, 0x81)), transport);
+
+        // Starting the transfer holds the master back until it hears from the other
+        // side as of then.
+        let mut steps = 0;
+        while master.step().unwrap() {
+            steps += 1;
+            assert!(steps < 100);
+        }
+        let start = master.clock();
+        let mut messages = Vec::new();
+        while let Some(message) = remote.try_recv().unwrap() {
+            messages.push(message);
+        }
+        assert_eq!(messages.last(), Some(&Message::Sync { clock: start }));
+
+        // The other side armed its byte long before, but the message is only
+        // delivered now.
+        remote
+            .send(Message::Armed {
+                clock: 16,
+                sb: 0x42,
+            })
+            .unwrap();
+        assert!(!master.step().unwrap());
+        remote.send(Message::Sync { clock: start }).unwrap();
+
+        // The master shifts out its byte against the one armed, and the byte is
+        // done within 8 bits of the normal clock.
+        let mut steps = 0;
+        let transfer = loop {
+            master.step().unwrap();
+            steps += 1;
+            assert!(steps < 2000 && master.clock() < start + 9 * 512);
+
+            if let Some(Message::Transfer { clock, sb }) = remote.try_recv().unwrap() {
+                break (clock, sb);
+            }
+        };
+        assert!(transfer.0 > start);
+        assert_eq!(transfer.1, 0x10);
+
+        for _ in 0..20 {
+            master.step().unwrap();
+        }
+        assert_eq!(master.gb.cpu_mut().mmu.get_byte(0xC000), 0x42);
+    }
+}
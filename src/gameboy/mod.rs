@@ -0,0 +1,348 @@
+// Platform-free entry point to the emulator core. Front ends (the wasm `Emulator`,
+// the headless runner, native embedders) drive a `GameBoy` one frame at a time and
+// pull video, audio and serial output from it.
+
+pub mod link;
+
+use crate::cpu::{CgbSpeed, Cpu};
+use crate::events::Event;
+pub use crate::gpu::blend::FrameBlending;
+pub use crate::gpu::color_correction::ColorCorrection;
+use crate::gpu::colorize;
+pub use crate::gpu::colorize::{ButtonCombo, CompatPalette};
+pub use crate::gpu::debug::{
+    HiddenLayers, OamEntry, TileMap, TilePalette, TILEMAP_SIZE, TILE_DATA_HEIGHT, TILE_DATA_WIDTH,
+};
+pub use crate::gpu::palette::{DmgPalette, DmgPreset, Shades};
+pub use crate::gpu::tiles::Sprite;
+use crate::infrared::InfraredDevice;
+pub use crate::joypad::Key;
+use crate::serial::SerialDevice;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// Number of clocks in one frame (154 lines of 456 clocks).
+pub const FRAME_CYCLES: usize = 70224;
+/// Rate at which `audio_samples` are produced.
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+pub struct GameBoy {
+    cpu: Cpu,
+    left_audio: Vec<f32>,
+    right_audio: Vec<f32>,
+}
+
+impl GameBoy {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let mut cpu = Cpu::new(rom);
+        cpu.simulate_bootrom();
+
+        Self {
+            cpu,
+            left_audio: Vec::new(),
+            right_audio: Vec::new(),
+        }
+    }
+
+    /// Runs until the next VBlank, or for one frame's worth of clocks while the
+    /// LCD is off.
+    pub fn step_frame(&mut self) {
+        let max_cycles = match self.cpu.mmu.cgb_mode.speed {
+            CgbSpeed::Normal => FRAME_CYCLES,
+            CgbSpeed::Double => FRAME_CYCLES * 2,
+        };
+
+        let mut cycles = 0;
+
+        while cycles < max_cycles {
+            cycles += self.step();
+
+            if self.cpu.mmu.gpu.vblank_event {
+                self.cpu.mmu.gpu.vblank_event = false;
+                break;
+            }
+        }
+    }
+
+    /// Executes a single instruction (or one halted step) and returns the number
+    /// of clocks it took.
+    pub fn step(&mut self) -> usize {
+        let cycles = self.cpu.tick();
+        self.collect_audio();
+        cycles
+    }
+
+    /// Event based driver used by the browser front end.
+    pub fn run_till_event(&mut self, max_cycles: usize) -> Event {
+        self.cpu.run_till_event(max_cycles)
+    }
+
+    /// The 160x144 RGBA screen.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.cpu.mmu.gpu.lcd
+    }
+
+    /// Drains the stereo samples produced since the last call.
+    pub fn audio_samples(&mut self) -> (Vec<f32>, Vec<f32>) {
+        (
+            std::mem::take(&mut self.left_audio),
+            std::mem::take(&mut self.right_audio),
+        )
+    }
+
+    /// Drains the bytes sent over the serial port since the last call.
+    pub fn serial_output(&mut self) -> Vec<u8> {
+        self.cpu.mmu.take_serial_output()
+    }
+
+    /// Plugs `device` into the link port, returning what was there before (a
+    /// `NoCable` to start with).
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        self.cpu.mmu.serial.connect(device)
+    }
+
+    /// Points the CGB infrared port at `device`, returning what was there before.
+    pub fn connect_infrared(&mut self, device: Box<dyn InfraredDevice>) -> Box<dyn InfraredDevice> {
+        self.cpu.mmu.infrared.connect(device)
+    }
+
+    /// Colours for DMG games, see `gpu/palette.rs`. Also undoes `colorize`. Has no
+    /// effect on CGB games.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.cpu.mmu.gpu.set_dmg_palette(palette);
+    }
+
+    pub fn dmg_palette(&self) -> DmgPalette {
+        *self.cpu.mmu.gpu.dmg_palette()
+    }
+
+    /// How CGB colours are shown, see `gpu/color_correction.rs`.
+    pub fn set_color_correction(&mut self, mode: ColorCorrection) {
+        self.cpu.mmu.gpu.set_color_correction(mode);
+    }
+
+    /// Emulates the slow LCD response some games rely on, see `gpu/blend.rs`.
+    pub fn set_frame_blending(&mut self, mode: FrameBlending) {
+        self.cpu.mmu.gpu.set_frame_blending(mode);
+    }
+
+    /// Colours a DMG game with the palette a CGB would pick, from its title or from
+    /// `combo` as if it was held at boot. Only the palette is emulated, the game
+    /// keeps running as on a DMG. See `gpu/colorize.rs`. Has no effect on CGB
+    /// games.
+    pub fn colorize(&mut self, combo: Option<ButtonCombo>) {
+        let palette = match combo {
+            Some(combo) => combo.palette(),
+            None => {
+                let header: Vec<u8> = (0..0x0150)
+                    .map(|addr| self.cpu.mmu.cartridge.get_byte(addr))
+                    .collect();
+                colorize::palette_for_rom(&header)
+            }
+        };
+
+        self.cpu.mmu.catch_up_all();
+        self.cpu.mmu.gpu.set_compat_palette(palette);
+    }
+
+    /// Every tile in VRAM drawn with `palette`, see `gpu/debug.rs`. `None` if
+    /// there's no such palette.
+    pub fn tile_data(&self, palette: TilePalette) -> Option<Vec<u8>> {
+        self.cpu.mmu.gpu.tile_data_image(palette)
+    }
+
+    /// The tilemap `map`, optionally with the viewport and window drawn over it,
+    /// see `gpu/debug.rs`.
+    pub fn tilemap(&self, map: TileMap, overlay: bool) -> Vec<u8> {
+        self.cpu.mmu.gpu.tilemap_image(map, overlay)
+    }
+
+    /// The 40 OAM entries, with which lines showed them and thumbnails.
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.cpu.mmu.gpu.oam_entries()
+    }
+
+    /// Just the thumbnail of OAM entry `index` (0-39), `None` for any other index.
+    pub fn sprite_thumbnail(&self, index: usize) -> Option<Vec<u8>> {
+        self.cpu.mmu.gpu.sprite_thumbnail(index)
+    }
+
+    /// 8 or 16, from LCDC.
+    pub fn obj_height(&self) -> usize {
+        self.cpu.mmu.gpu.obj_height()
+    }
+
+    /// How many objects each line picked, 10 meaning any more were dropped.
+    pub fn sprites_per_line(&self) -> Vec<u8> {
+        self.cpu.mmu.gpu.sprites_per_line()
+    }
+
+    /// The four colours of `palette` as they're shown, `None` if there's no such
+    /// palette.
+    pub fn palette(&self, palette: TilePalette) -> Option<Shades> {
+        self.cpu.mmu.gpu.palette_rgb(palette)
+    }
+
+    /// A shade for DMG palettes, a BGR555 colour for CGB ones. `None` if `palette`
+    /// or `value` is out of range.
+    pub fn palette_entry(&self, palette: TilePalette, value: usize) -> Option<u16> {
+        self.cpu.mmu.gpu.palette_entry(palette, value)
+    }
+
+    /// Changes colour `value` of `palette` from here on, as if the game had.
+    /// Returns false, changing nothing, if either is out of range.
+    pub fn set_palette_entry(&mut self, palette: TilePalette, value: usize, entry: u16) -> bool {
+        self.cpu.mmu.catch_up_all();
+        self.cpu.mmu.gpu.set_palette_entry(palette, value, entry)
+    }
+
+    /// Leaves layers or single objects out of the screen without changing timing.
+    pub fn set_hidden_layers(&mut self, hidden: HiddenLayers) {
+        self.cpu.mmu.catch_up_all();
+        self.cpu.mmu.gpu.set_hidden_layers(hidden);
+    }
+
+    pub fn hidden_layers(&self) -> HiddenLayers {
+        self.cpu.mmu.gpu.hidden_layers()
+    }
+
+    pub fn key_down(&mut self, key: Key) {
+        self.cpu.mmu.joypad.press_key(key);
+    }
+
+    pub fn key_up(&mut self, key: Key) {
+        self.cpu.mmu.joypad.release_key(key);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    fn collect_audio(&mut self) {
+        if let (Some(left), Some(right)) = self.cpu.mmu.apu.get_next_buffer() {
+            self.left_audio.extend(left);
+            self.right_audio.extend(right);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::R8;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // nop; jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // ld a,$80; ldh (NR52),a; ld a,$FF; ldh (NR51),a; ld a,$77; ldh (NR50),a; jr -2
+        rom[0x150..0x15E].copy_from_slice(&[
+            0x3E, 0x80, 0xE0, 0x26, 0x3E, 0xFF, 0xE0, 0x25, 0x3E, 0x77, 0xE0, 0x24, 0x18, 0xFE,
+        ]);
+        rom
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let mut gb = GameBoy::new(test_rom());
+
+        for _ in 0..10 {
+            gb.step_frame();
+        }
+
+        assert_eq!(gb.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+
+        let (left, right) = gb.audio_samples();
+        assert!(!left.is_empty());
+        assert_eq!(left.len(), right.len());
+        assert!(gb.audio_samples().0.is_empty());
+    }
+
+    #[test]
+    fn test_colorize() {
+        let mut gb = GameBoy::new(test_rom());
+        // DMG colours until asked for.
+        let dmg = gb.palette(TilePalette::Bgp);
+        assert_eq!(gb.palette_entry(TilePalette::CgbBg(0), 1), Some(0));
+
+        // Not a Nintendo title, so the CGB's default palette.
+        gb.colorize(None);
+        assert_eq!(gb.palette_entry(TilePalette::CgbBg(0), 1), Some(0x1BEF));
+        assert_ne!(gb.palette(TilePalette::Bgp), dmg);
+
+        gb.colorize(Some(ButtonCombo::LeftB));
+        assert_eq!(gb.palette_entry(TilePalette::CgbObj(1), 1), Some(0x5294));
+
+        gb.set_dmg_palette(gb.dmg_palette());
+        assert_eq!(gb.palette(TilePalette::Bgp), dmg);
+    }
+
+    // Keeps the timer, STAT and VBlank interrupts firing with a square wave playing,
+    // and logs LY ^ TIMA after every interrupt.
+    fn interrupt_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // VBlank, STAT and timer: inc c/d/e; reti
+        rom[0x40..0x42].copy_from_slice(&[0x0C, 0xD9]);
+        rom[0x48..0x4A].copy_from_slice(&[0x14, 0xD9]);
+        rom[0x50..0x52].copy_from_slice(&[0x1C, 0xD9]);
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x180].copy_from_slice(&[
+            0x3E, 0x80, 0xE0, 0x26, // ld a,$80; ldh (NR52),a
+            0x3E, 0xFF, 0xE0, 0x25, // ld a,$FF; ldh (NR51),a
+            0x3E, 0x77, 0xE0, 0x24, // ld a,$77; ldh (NR50),a
+            0x3E, 0xF0, 0xE0, 0x12, // ld a,$F0; ldh (NR12),a
+            0x3E, 0x87, 0xE0, 0x14, // ld a,$87; ldh (NR14),a
+            0x3E, 0x05, 0xE0, 0x07, // ld a,$05; ldh (TAC),a
+            0x3E, 0x08, 0xE0, 0x41, // ld a,$08; ldh (STAT),a
+            0x3E, 0x07, 0xE0, 0xFF, // ld a,$07; ldh (IE),a
+            0x21, 0x00, 0xC0, // ld hl,$C000
+            0xFB, // ei
+            0x76, // loop: halt
+            0xF0, 0x44, // ldh a,(LY)
+            0x47, // ld b,a
+            0xF0, 0x05, // ldh a,(TIMA)
+            0xA8, // xor b
+            0x22, // ld (hl+),a
+            0xCB, 0xAC, // res 5,h
+            0x18, 0xF4, // jr loop
+        ]);
+        rom
+    }
+
+    #[test]
+    fn test_lazy_timing_matches_lockstep() {
+        let mut lazy = GameBoy::new(interrupt_rom());
+        let mut lockstep = GameBoy::new(interrupt_rom());
+        lockstep.cpu_mut().mmu.set_lockstep(true);
+
+        let mut cycles = 0;
+        while cycles < 30 * FRAME_CYCLES {
+            let step = lazy.step();
+            assert_eq!(step, lockstep.step());
+            assert_eq!(lazy.cpu().pc, lockstep.cpu().pc);
+            cycles += step;
+        }
+
+        for r in &[R8::A, R8::B, R8::C, R8::D, R8::E, R8::H, R8::L] {
+            assert_eq!(lazy.cpu().get_r8(r), lockstep.cpu().get_r8(r));
+        }
+        // VBlank interrupts were taken.
+        assert!(lazy.cpu().get_r8(&R8::C) > 0);
+
+        for addr in 0xC000..0xE000 {
+            assert_eq!(
+                lazy.cpu_mut().mmu.get_byte(addr),
+                lockstep.cpu_mut().mmu.get_byte(addr)
+            );
+        }
+
+        assert_eq!(lazy.framebuffer(), lockstep.framebuffer());
+        let audio = lazy.audio_samples();
+        assert!(!audio.0.is_empty());
+        assert_eq!(audio, lockstep.audio_samples());
+    }
+}
@@ -0,0 +1,113 @@
+// LCD ghosting. The DMG screen takes several frames to settle on a new shade, which
+// some games rely on: they flicker sprites every other frame to get transparency
+// or extra colours. Once a frame is complete the `lcd` buffer is blended with what
+// came before:
+//  - `Blend` shows the average of this frame and the last one,
+//  - `Ghosting` moves each pixel a fraction (`response`) of the way to its new
+//    colour every frame, so changes fade in and out over several frames.
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FrameBlending {
+    #[default]
+    Off,
+    Blend,
+    /// `response` is between 0 (never changes) and 1 (no ghosting).
+    Ghosting {
+        response: f32,
+    },
+}
+
+pub struct FrameBlender {
+    mode: FrameBlending,
+    // The last frame drawn for `Blend`, the last frame shown for `Ghosting`.
+    history: Vec<u8>,
+}
+
+impl FrameBlender {
+    pub fn new(mode: FrameBlending) -> Self {
+        Self {
+            mode,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> FrameBlending {
+        self.mode
+    }
+
+    /// Blends a freshly drawn RGBA frame in place.
+    pub fn apply(&mut self, lcd: &mut [u8]) {
+        // Out of 256, how far each pixel moves towards its new colour.
+        let weight = match self.mode {
+            FrameBlending::Off => return,
+            FrameBlending::Blend => 128,
+            FrameBlending::Ghosting { response } => (response.clamp(0.0, 1.0) * 256.0) as i32,
+        };
+
+        if self.history.len() != lcd.len() {
+            self.history = lcd.to_vec();
+            return;
+        }
+
+        let blend = self.mode == FrameBlending::Blend;
+
+        for (pixel, old) in lcd.iter_mut().zip(self.history.iter_mut()) {
+            let diff = *pixel as i32 - *old as i32;
+            // Rounded away from zero so pixels always reach their new colour.
+            let step = (diff * weight + diff.signum() * 255) / 256;
+            let shown = (*old as i32 + step) as u8;
+
+            *old = if blend { *pixel } else { shown };
+            *pixel = shown;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        let mut blender = FrameBlender::new(FrameBlending::Blend);
+        let mut frame = vec![0, 255, 100, 255];
+        blender.apply(&mut frame);
+        assert_eq!(frame, vec![0, 255, 100, 255]);
+
+        let mut frame = vec![255, 0, 100, 255];
+        blender.apply(&mut frame);
+        assert_eq!(frame, vec![128, 127, 100, 255]);
+
+        // Blended with the last frame drawn, not the last one shown.
+        let mut frame = vec![255, 0, 100, 255];
+        blender.apply(&mut frame);
+        assert_eq!(frame, vec![255, 0, 100, 255]);
+    }
+
+    #[test]
+    fn test_ghosting_settles() {
+        let mut blender = FrameBlender::new(FrameBlending::Ghosting { response: 0.25 });
+        blender.apply(&mut [0; 4]);
+
+        let mut shown = Vec::new();
+        for _ in 0..40 {
+            let mut frame = vec![255; 4];
+            blender.apply(&mut frame);
+            shown.push(frame[0]);
+        }
+
+        assert_eq!(shown[0], 64);
+        assert!(shown.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(shown.last(), Some(&255));
+    }
+
+    #[test]
+    fn test_off() {
+        let mut blender = FrameBlender::new(FrameBlending::Off);
+        blender.apply(&mut [0; 4]);
+
+        let mut frame = vec![255; 4];
+        blender.apply(&mut frame);
+        assert_eq!(frame, vec![255; 4]);
+    }
+}
@@ -0,0 +1,154 @@
+// How CGB colours (5 bits per channel) turn into RGB888. The CGB screen doesn't
+// respond linearly and its channels bleed into each other, so scaling the channels
+// straight up gets the midtones wrong and looks too saturated. The corrections
+// below follow SameBoy:
+//  - `CorrectCurves` maps each channel through the measured brightness curve,
+//  - `EmulateHardware` also bleeds blue into green, mixed in linear light, as the
+//    screen does,
+//  - `PreserveBrightness` is the same, rescaled so the brightest and darkest
+//    channels keep their uncorrected values.
+// Every colour goes through a 32K table built once per mode, so drawing a pixel is
+// still a single lookup.
+//
+// References: https://github.com/LIJI32/SameBoy/blob/master/Core/display.c
+
+use crate::gpu::palette::Rgb;
+
+const COLORS: usize = 0x8000;
+const GAMMA: f64 = 2.2;
+
+// Brightness of each channel level on the CGB screen.
+const CHANNEL_CURVE: [u8; 32] = [
+    0, 6, 12, 20, 28, 36, 45, 56, 66, 76, 88, 100, 113, 125, 137, 149, 161, 172, 182, 192, 202,
+    210, 218, 225, 232, 238, 243, 247, 250, 252, 254, 255,
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorCorrection {
+    #[default]
+    None,
+    CorrectCurves,
+    EmulateHardware,
+    PreserveBrightness,
+}
+
+impl ColorCorrection {
+    pub const ALL: [ColorCorrection; 4] = [
+        ColorCorrection::None,
+        ColorCorrection::CorrectCurves,
+        ColorCorrection::EmulateHardware,
+        ColorCorrection::PreserveBrightness,
+    ];
+
+    fn convert(&self, color: u16) -> Rgb {
+        let r = (color & 0x1F) as usize;
+        let g = (color >> 5 & 0x1F) as usize;
+        let b = (color >> 10 & 0x1F) as usize;
+
+        if *self == ColorCorrection::None {
+            let scale = |c: usize| ((c << 3) | (c >> 2)) as u8;
+            return (scale(r), scale(g), scale(b));
+        }
+
+        let (r, g, b) = (CHANNEL_CURVE[r], CHANNEL_CURVE[g], CHANNEL_CURVE[b]);
+        if *self == ColorCorrection::CorrectCurves || g == b {
+            return (r, g, b);
+        }
+
+        let linear = |c: u8| (c as f64 / 255.0).powf(GAMMA);
+        let new_g = ((linear(g) * 3.0 + linear(b)) / 4.0).powf(1.0 / GAMMA) * 255.0;
+        let (new_r, new_g, new_b) = (r as u32, new_g.round() as u32, b as u32);
+
+        if *self == ColorCorrection::EmulateHardware {
+            return (new_r as u8, new_g as u8, new_b as u8);
+        }
+
+        // Scale the brightest channel back to where it was, then the darkest.
+        let old_max = r.max(g).max(b) as u32;
+        let new_max = new_r.max(new_g).max(new_b);
+        let scale = |c: u32| (c * old_max).checked_div(new_max).unwrap_or(c);
+        let (new_r, new_g, new_b) = (scale(new_r), scale(new_g), scale(new_b));
+
+        let old_min = r.min(g).min(b) as u32;
+        let new_min = new_r.min(new_g).min(new_b);
+        let stretch = |c: u32| {
+            if new_min == 0xFF {
+                c as u8
+            } else {
+                (0xFF - (0xFF - c) * (0xFF - old_min) / (0xFF - new_min)) as u8
+            }
+        };
+
+        (stretch(new_r), stretch(new_g), stretch(new_b))
+    }
+}
+
+/// Every CGB colour, converted with one `ColorCorrection`.
+pub struct ColorLut {
+    mode: ColorCorrection,
+    table: Vec<Rgb>,
+}
+
+impl ColorLut {
+    pub fn new(mode: ColorCorrection) -> Self {
+        Self {
+            mode,
+            table: (0..COLORS)
+                .map(|color| mode.convert(color as u16))
+                .collect(),
+        }
+    }
+
+    pub fn mode(&self) -> ColorCorrection {
+        self.mode
+    }
+
+    #[inline]
+    pub fn get(&self, color: u16) -> Rgb {
+        self.table[(color & 0x7FFF) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_correction_scales_channels() {
+        let lut = ColorLut::new(ColorCorrection::None);
+        assert_eq!(lut.get(0x7FFF), (255, 255, 255));
+        assert_eq!(lut.get(0x0010), (132, 0, 0));
+        assert_eq!(lut.get(0x7C00), (0, 0, 255));
+    }
+
+    #[test]
+    fn test_corrections() {
+        for &mode in ColorCorrection::ALL.iter() {
+            let lut = ColorLut::new(mode);
+            assert_eq!(lut.get(0x0000), (0, 0, 0));
+            assert_eq!(lut.get(0x7FFF), (255, 255, 255));
+            // Greys stay grey.
+            let (r, g, b) = lut.get(0x4210);
+            assert!(r == g && g == b);
+        }
+
+        // Mid levels follow the screen's curve rather than a straight scale.
+        assert_eq!(
+            ColorLut::new(ColorCorrection::CorrectCurves).get(0x0010),
+            (161, 0, 0)
+        );
+
+        // Pure green loses some brightness to the blue it's mixed with, which
+        // preserving brightness gives back.
+        assert_eq!(
+            ColorLut::new(ColorCorrection::EmulateHardware)
+                .get(0x03E0)
+                .1,
+            224
+        );
+        assert_eq!(
+            ColorLut::new(ColorCorrection::PreserveBrightness).get(0x03E0),
+            (0, 255, 0)
+        );
+    }
+}
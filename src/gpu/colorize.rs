@@ -0,0 +1,427 @@
+// Colours for DMG games the way the CGB boot ROM picks them. For games licensed by
+// Nintendo it sums the 16 title bytes (0134-0143) and looks the sum up in a table,
+// using the fourth letter of the title where two games share a sum. Everything
+// else gets the default palette, and holding a direction with A or B while the
+// logo scrolls picks one of 12 palettes instead.
+//
+// The boot ROM writes the colours to BG palette 0 and OBJ palettes 0 and 1, then
+// locks the CGB into DMG compatibility mode where BGP, OBP0 and OBP1 index into
+// them. Only the palettes are emulated: `Gpu::set_compat_palette` loads them and
+// routes the DMG shades through them, but the game still runs on DMG hardware,
+// with DMG timing and without the rest of compatibility mode.
+//
+// The tables below are the boot ROM's: 30 palettes of four colours, 51 ways of
+// combining three of them, and the title sums pointing at those combinations.
+//
+// References: https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes
+//             https://github.com/LIJI32/SameBoy/blob/master/BootROMs/cgb_boot.asm
+
+// CGB colours, 5 bits each of blue, green and red.
+type CgbShades = [u16; 4];
+
+#[rustfmt::skip]
+const PALETTES: [CgbShades; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+// Index of the first colour of a palette.
+const fn p(palette: usize) -> usize {
+    palette * 4
+}
+
+// OBJ0, OBJ1 and background, as the index of their first colour in `PALETTES`.
+// Three combinations start part way into a palette, like they do in the boot ROM.
+#[rustfmt::skip]
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (p(4), p(4), p(29)),
+    (p(18), p(18), p(18)),
+    (p(20), p(20), p(20)),
+    (p(24), p(24), p(24)),
+    (p(9), p(9), p(9)),
+    (p(0), p(0), p(0)),
+    (p(27), p(27), p(27)),
+    (p(5), p(5), p(5)),
+    (p(12), p(12), p(12)),
+    (p(26), p(26), p(26)),
+    (p(16), p(8), p(8)),
+    (p(4), p(28), p(28)),
+    (p(4), p(2), p(2)),
+    (p(3), p(4), p(4)),
+    (p(4), p(29), p(29)),
+    (p(28), p(4), p(28)),
+    (p(2), p(17), p(2)),
+    (p(16), p(16), p(8)),
+    (p(4), p(4), p(7)),
+    (p(4), p(4), p(18)),
+    (p(4), p(4), p(20)),
+    (p(19), p(19), p(9)),
+    (p(4) - 1, p(4) - 1, p(11)),
+    (p(17), p(17), p(2)),
+    (p(4), p(4), p(2)),
+    (p(4), p(4), p(3)),
+    (p(28), p(28), p(0)),
+    (p(3), p(3), p(0)),
+    (p(0), p(0), p(1)),
+    (p(18), p(22), p(18)),
+    (p(20), p(22), p(20)),
+    (p(24), p(22), p(24)),
+    (p(16), p(22), p(8)),
+    (p(17), p(4), p(13)),
+    (p(28) - 1, p(0), p(14)),
+    (p(28) - 1, p(4), p(15)),
+    (p(19), p(22), p(9)),
+    (p(16), p(28), p(10)),
+    (p(4), p(23), p(28)),
+    (p(17), p(22), p(2)),
+    (p(4), p(0), p(2)),
+    (p(4), p(28), p(3)),
+    (p(28), p(3), p(0)),
+    (p(3), p(28), p(4)),
+    (p(21), p(28), p(4)),
+    (p(3), p(28), p(0)),
+    (p(25), p(3), p(28)),
+    (p(0), p(28), p(8)),
+    (p(4), p(3), p(28)),
+    (p(28), p(3), p(6)),
+    (p(4), p(28), p(29)),
+];
+
+// Used when nothing in `TITLES` matches.
+const DEFAULT: usize = 0;
+
+// Title sum, fourth letter if the sum is shared, index in `COMBINATIONS`.
+#[rustfmt::skip]
+const TITLES: [(u8, Option<u8>, usize); 93] = [
+    (0x88, None, 4),         // ALLEY WAY
+    (0x16, None, 5),         // YAKUMAN
+    (0x36, None, 35),        // BASEBALL, GAME&WATCH 2
+    (0xD1, None, 34),        // TENNIS
+    (0xDB, None, 3),         // TETRIS
+    (0xF2, None, 31),        // QIX
+    (0x3C, None, 15),        // DR.MARIO
+    (0x8C, None, 10),        // RADARMISSION
+    (0x92, None, 5),         // F1RACE
+    (0x3D, None, 19),        // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7),         // X
+    (0xC9, None, 37),        // MARIOLAND2
+    (0x3E, None, 30),        // YOSSY NO COOKIE
+    (0x70, None, 44),        // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31),        // TETRIS FLASH
+    (0x19, None, 20),        // DONKEY KONG
+    (0x35, None, 5),         // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13),        // POKEMON RED, GAMEBOYCAMERA G
+    (0xAA, None, 14),        // POKEMON GREEN
+    (0x75, None, 5),         // PICROSS 2
+    (0x95, None, 29),        // YOSSY NO PANEPON
+    (0x99, None, 5),         // KIRAKIRA KIDS
+    (0x34, None, 18),        // GAMEBOY GALLERY
+    (0x6F, None, 9),         // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2),         // BALLOON KID
+    (0x97, None, 26),        // KINGOFTHEZOO
+    (0x4B, None, 25),        // DMG FOOTBALL
+    (0x90, None, 25),        // WORLD CUP
+    (0x17, None, 41),        // OTHELLO
+    (0x10, None, 42),        // SUPER RC PRO-AM
+    (0x39, None, 26),        // DYNABLASTER
+    (0xF7, None, 45),        // BOY AND BLOB GB2
+    (0xF6, None, 42),        // MEGAMAN
+    (0xA2, None, 45),        // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38),        // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42),        // LOLO2
+    (0xE0, None, 30),        // YOSHI'S COOKIE
+    (0x8B, None, 41),        // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34),        // TOPRANKINGTENNIS
+    (0x0C, None, 5),         // MANSELL
+    (0x29, None, 42),        // MEGAMAN3
+    (0xE8, None, 6),         // SPACE INVADERS
+    (0xB7, None, 5),         // GAME&WATCH
+    (0x86, None, 33),        // DONKEYKONGLAND95
+    (0x9A, None, 25),        // ASTEROIDS/MISCMD
+    (0x52, None, 42),        // STREET FIGHTER 2
+    (0x01, None, 42),        // DEFENDER/JOUST
+    (0x9D, None, 40),        // KILLERINSTINCT95
+    (0x71, None, 2),         // TETRIS BLAST
+    (0x9C, None, 16),        // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42),        // BA.TOSHINDEN
+    (0x6D, None, 42),        // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0),         // TETRIS PLUS
+    (0x6B, None, 39),        // DONKEYKONGLAND 3
+    (0xB3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22),  // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25),  // GOLF
+    (0xA5, Some(b'A'), 6),   // SOLARSTRIKER
+    (0xC6, Some(b'A'), 32),  // GBWARS
+    (0xD3, Some(b'R'), 12),  // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11),  // POKEMON BLUE
+    (0x18, Some(b'K'), 39),  // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18),  // GAMEBOY GALLERY2
+    (0x6A, Some(b'K'), 39),  // DONKEYKONGLAND 2
+    (0xBF, Some(b' '), 24),  // KID ICARUS
+    (0x0D, Some(b'R'), 31),  // TETRIS2
+    (0xF4, Some(b'-'), 50),
+    (0xB3, Some(b'U'), 17),  // MOGURANYA
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),   // GALAGA&GALAXIAN
+    (0xA5, Some(b'R'), 27),  // BT2RAGNAROKWORLD
+    (0xC6, Some(b' '), 0),   // KEN GRIFFEY JR
+    (0xD3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41),  // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41),  // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 34),  // MILLI/CENTI/PEDE
+    (0x6A, Some(b'I'), 23),  // MARIO & YOSHI
+    (0xBF, Some(b'C'), 18),  // SOCCER
+    (0x0D, Some(b'E'), 29),  // POKEBOM
+    (0xF4, Some(b' '), 28),  // G&W GALLERY
+    (0xB3, Some(b'R'), 12),  // TETRIS ATTACK
+];
+
+/// The colours the CGB boot ROM leaves in BG palette 0 and OBJ palettes 0 and 1
+/// for a DMG game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompatPalette {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl CompatPalette {
+    fn from_combination(index: usize) -> Self {
+        let (obj0, obj1, bg) = COMBINATIONS[index];
+        let shades = |first: usize| {
+            let mut colors = [0; 4];
+            for (i, color) in colors.iter_mut().enumerate() {
+                *color = PALETTES[(first + i) / 4][(first + i) % 4];
+            }
+            colors
+        };
+
+        Self {
+            bg: shades(bg),
+            obj0: shades(obj0),
+            obj1: shades(obj1),
+        }
+    }
+}
+
+/// What's held while the boot logo scrolls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonCombo {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl ButtonCombo {
+    pub const ALL: [ButtonCombo; 12] = [
+        ButtonCombo::Up,
+        ButtonCombo::UpA,
+        ButtonCombo::UpB,
+        ButtonCombo::Left,
+        ButtonCombo::LeftA,
+        ButtonCombo::LeftB,
+        ButtonCombo::Down,
+        ButtonCombo::DownA,
+        ButtonCombo::DownB,
+        ButtonCombo::Right,
+        ButtonCombo::RightA,
+        ButtonCombo::RightB,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ButtonCombo::Up => "Up: brown",
+            ButtonCombo::UpA => "Up+A: red",
+            ButtonCombo::UpB => "Up+B: dark brown",
+            ButtonCombo::Left => "Left: blue",
+            ButtonCombo::LeftA => "Left+A: dark blue",
+            ButtonCombo::LeftB => "Left+B: greyscale",
+            ButtonCombo::Down => "Down: pastel",
+            ButtonCombo::DownA => "Down+A: orange",
+            ButtonCombo::DownB => "Down+B: yellow",
+            ButtonCombo::Right => "Right: green",
+            ButtonCombo::RightA => "Right+A: dark green",
+            ButtonCombo::RightB => "Right+B: inverted",
+        }
+    }
+
+    pub fn palette(&self) -> CompatPalette {
+        CompatPalette::from_combination(match self {
+            ButtonCombo::Up => 5,
+            ButtonCombo::UpA => 43,
+            ButtonCombo::UpB => 28,
+            ButtonCombo::Left => 48,
+            ButtonCombo::LeftA => 40,
+            ButtonCombo::LeftB => 7,
+            ButtonCombo::Down => 8,
+            ButtonCombo::DownA => 3,
+            ButtonCombo::DownB => 49,
+            ButtonCombo::Right => 1,
+            ButtonCombo::RightA => DEFAULT,
+            ButtonCombo::RightB => 6,
+        })
+    }
+}
+
+/// The palette a CGB picks for a DMG game, from its header (at least the first
+/// $150 bytes of the ROM).
+pub fn palette_for_rom(rom: &[u8]) -> CompatPalette {
+    let title = &rom[0x0134..=0x0143];
+    let nintendo = match rom[0x014B] {
+        0x01 => true,
+        0x33 => &rom[0x0144..=0x0145] == b"01",
+        _ => false,
+    };
+
+    if !nintendo {
+        return CompatPalette::from_combination(DEFAULT);
+    }
+
+    let sum = title.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+    let combination = TITLES
+        .iter()
+        .find(|(title_sum, fourth, _)| {
+            *title_sum == sum && fourth.is_none_or(|letter| letter == title[3])
+        })
+        .map_or(DEFAULT, |&(_, _, combination)| combination);
+
+    CompatPalette::from_combination(combination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(title: &str, licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x150];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x014B] = licensee;
+        rom
+    }
+
+    fn lookup(title: &str) -> CompatPalette {
+        palette_for_rom(&header(title, 0x01))
+    }
+
+    #[test]
+    fn test_title_lookup() {
+        let red = lookup("POKEMON RED");
+        assert_eq!(red.bg, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(red.obj0, [0x7FFF, 0x1BEF, 0x0200, 0x0000]);
+
+        let zelda = lookup("ZELDA");
+        assert_eq!(zelda.obj0, [0x7FFF, 0x03E0, 0x0206, 0x0120]);
+        assert_eq!(zelda.obj1, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+
+        // Starts on the last colour of one palette and runs into the next.
+        assert_eq!(
+            lookup("SUPER MARIOLAND").obj0,
+            [0x0000, 0x7FFF, 0x421F, 0x1CF2]
+        );
+
+        // Same sum as POKEMON BLUE, a fourth letter nothing else uses.
+        let mut other = header("POKEMON BLUE", 0x01);
+        other[0x0137] -= 1;
+        other[0x0138] += 1;
+        assert_eq!(palette_for_rom(&other), ButtonCombo::RightA.palette());
+
+        // Only Nintendo games are looked up.
+        let blue = lookup("POKEMON BLUE");
+        assert_eq!(
+            palette_for_rom(&header("POKEMON BLUE", 0x08)),
+            ButtonCombo::RightA.palette()
+        );
+        let mut new_licensee = header("POKEMON BLUE", 0x33);
+        new_licensee[0x0144..=0x0145].copy_from_slice(b"01");
+        assert_eq!(palette_for_rom(&new_licensee), blue);
+    }
+
+    #[test]
+    fn test_shared_sums() {
+        let pairs = [
+            ("POKEMON BLUE", "VEGAS STAKES"),
+            ("TETRIS2", "POKEBOM"),
+            ("KID ICARUS", "SOCCER"),
+            ("MOGURANYA", "TETRIS ATTACK"),
+            ("GBWARS", "KEN GRIFFEY JR"),
+            ("GAMEBOY GALLERY2", "MILLI/CENTI/PEDE"),
+        ];
+
+        for (first, second) in pairs.iter() {
+            let sum = |title: &str| title.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            assert_eq!(sum(first), sum(second));
+            assert_ne!(lookup(first), lookup(second), "{} / {}", first, second);
+        }
+
+        assert_eq!(lookup("POKEMON BLUE").bg, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+        assert_eq!(lookup("VEGAS STAKES").bg, [0x7FFF, 0x1BEF, 0x0200, 0x0000]);
+        assert_eq!(lookup("KEN GRIFFEY JR"), ButtonCombo::RightA.palette());
+    }
+
+    #[test]
+    fn test_button_combos() {
+        assert_eq!(
+            ButtonCombo::LeftB.palette().bg,
+            [0x7FFF, 0x5294, 0x294A, 0x0000]
+        );
+        assert_eq!(ButtonCombo::RightB.palette().bg[0], 0x0000);
+        assert_eq!(
+            ButtonCombo::DownB.palette().obj1,
+            [0x7FFF, 0x1BEF, 0x0200, 0x0000]
+        );
+        assert_eq!(
+            ButtonCombo::RightA.palette().bg,
+            [0x7FFF, 0x1BEF, 0x6180, 0x0000]
+        );
+    }
+}
@@ -0,0 +1,764 @@
+// Debugger views of VRAM. They're drawn straight from the Gpu's memory and only
+// borrow it, so looking never changes anything the game can see, whatever mode
+// the PPU is in. Images are RGBA like the screen.
+
+use crate::cpu::EmulationMode;
+use crate::gpu::palette::{Rgb, Shades};
+use crate::gpu::tiles::Sprite;
+use crate::gpu::{Gpu, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM_OFFSET};
+use std::fmt;
+
+const TILES_PER_BANK: usize = 384;
+const TILES_PER_ROW: usize = 16;
+const DEPTH: usize = 4;
+
+pub const TILE_DATA_WIDTH: usize = TILES_PER_ROW * 8;
+/// Bank 0 on top, bank 1 below it.
+pub const TILE_DATA_HEIGHT: usize = 2 * TILES_PER_BANK / TILES_PER_ROW * 8;
+/// Both sides of the square tilemap image.
+pub const TILEMAP_SIZE: usize = 256;
+
+const VIEWPORT_COLOR: Rgb = (255, 0, 0);
+const WINDOW_COLOR: Rgb = (0, 0, 255);
+const PRIORITY_COLOR: Rgb = (255, 255, 0);
+
+/// One of the 40 OAM entries, and what the OAM search of each line made of it the
+/// last time it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OamEntry {
+    pub index: usize,
+    /// Position as it is in OAM, so X is 8 and Y 16 more than on screen.
+    pub sprite: Sprite,
+    /// Lines whose 10 objects it was one of.
+    pub selected_lines: Vec<u8>,
+    /// Lines it's on but left out of because 10 objects came before it.
+    pub dropped_lines: Vec<u8>,
+    /// RGBA, 8 pixels wide and 8 or 16 high like objects are, transparent where
+    /// the object is.
+    pub thumbnail: Vec<u8>,
+}
+
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sprite = &self.sprite;
+
+        write!(
+            f,
+            "#{:02} X={:3} Y={:3} tile={:02X} OBP{} CGB={} bank={} flip={}{} {} lines={} dropped={}",
+            self.index,
+            sprite.x,
+            sprite.y,
+            sprite.number,
+            sprite.obp1 as u8,
+            sprite.obp_num,
+            sprite.vram_bank,
+            if sprite.mirror_horizontal { "X" } else { "-" },
+            if sprite.mirror_vertical { "Y" } else { "-" },
+            if sprite.obj_to_bg_prio != 0 { "behind" } else { "above" },
+            self.selected_lines.len(),
+            self.dropped_lines.len(),
+        )
+    }
+}
+
+/// Layers left out of the screen. The PPU fetches and times everything as usual,
+/// only the pixels it writes change: a hidden background or window shows the
+/// lightest shade (with objects behind it showing through), a hidden object lets
+/// the ones under it show.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HiddenLayers {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+    /// A bit for each OAM entry.
+    pub oam_entries: u64,
+}
+
+/// One of the two 32x32 tilemaps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileMap {
+    /// At $9800.
+    Low,
+    /// At $9C00.
+    High,
+}
+
+impl TileMap {
+    pub fn addr(self) -> u16 {
+        match self {
+            TileMap::Low => 0x9800,
+            TileMap::High => 0x9C00,
+        }
+    }
+}
+
+/// Which colours tiles are drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TilePalette {
+    Bgp,
+    Obp0,
+    Obp1,
+    /// One of the eight CGB background palettes.
+    CgbBg(usize),
+    /// One of the eight CGB object palettes.
+    CgbObj(usize),
+}
+
+impl TilePalette {
+    fn exists(self) -> bool {
+        match self {
+            TilePalette::CgbBg(n) | TilePalette::CgbObj(n) => n < 8,
+            _ => true,
+        }
+    }
+}
+
+impl Gpu {
+    /// All 384 tiles of both VRAM banks, 16 to a row, `TILE_DATA_WIDTH` by
+    /// `TILE_DATA_HEIGHT`. `None` if there's no such palette.
+    pub fn tile_data_image(&self, palette: TilePalette) -> Option<Vec<u8>> {
+        let colors = self.palette_rgb(palette)?;
+        let mut image = vec![0; TILE_DATA_WIDTH * TILE_DATA_HEIGHT * DEPTH];
+
+        for bank in 0..2 {
+            for tile in 0..TILES_PER_BANK {
+                let n = bank * TILES_PER_BANK + tile;
+                let x = n % TILES_PER_ROW * 8;
+                let y = n / TILES_PER_ROW * 8;
+
+                for row in 0..8 {
+                    let (lo, hi) = self.tile_row(bank, tile, row);
+
+                    for col in 0..8 {
+                        let color = colors[tile_pixel(lo, hi, col) as usize];
+                        put_pixel(&mut image, TILE_DATA_WIDTH, x + col, y + row, color);
+                    }
+                }
+            }
+        }
+
+        Some(image)
+    }
+
+    /// The 32x32 tiles of the tilemap `map`, drawn with the tile
+    /// data LCDC selects and, on the CGB, each tile's attributes. With `overlay` the
+    /// screen's SCX/SCY viewport is outlined in red, and tiles drawn over objects
+    /// are marked in yellow in their top left corner. If this is the window's map,
+    /// the part of the window on screen (from WX/WY) is outlined in blue.
+    pub fn tilemap_image(&self, map: TileMap, overlay: bool) -> Vec<u8> {
+        let mut image = vec![0; TILEMAP_SIZE * TILEMAP_SIZE * DEPTH];
+        let dmg_colors = self.shades(TilePalette::Bgp);
+
+        for i in 0..32 * 32 {
+            let addr = (map.addr() - VRAM_OFFSET) as usize + i;
+            let idx = self.vram0[addr];
+            let attr = match self.emu_mode {
+                EmulationMode::Dmg => 0,
+                EmulationMode::Cgb => self.vram1[addr],
+            };
+
+            let colors = match self.emu_mode {
+                EmulationMode::Dmg => dmg_colors,
+                EmulationMode::Cgb => self.shades(TilePalette::CgbBg((attr & 0x7) as usize)),
+            };
+            let tile =
+                (self.tiledata_addr(self.lcdc.tiledata_sel, idx) - VRAM_OFFSET) as usize / 16;
+            let bank = (attr >> 3 & 0x1) as usize;
+            let (x, y) = (i % 32 * 8, i / 32 * 8);
+
+            for row in 0..8 {
+                let flipped_row = if attr & 0x40 != 0 { row ^ 0x7 } else { row };
+                let (lo, hi) = self.tile_row(bank, tile, flipped_row);
+
+                for col in 0..8 {
+                    let flipped_col = if attr & 0x20 != 0 { col ^ 0x7 } else { col };
+                    let color = colors[tile_pixel(lo, hi, flipped_col) as usize];
+                    put_pixel(&mut image, TILEMAP_SIZE, x + col, y + row, color);
+                }
+            }
+
+            if overlay && attr & 0x80 != 0 {
+                for (dx, dy) in &[(0, 0), (1, 0), (0, 1)] {
+                    put_pixel(&mut image, TILEMAP_SIZE, x + dx, y + dy, PRIORITY_COLOR);
+                }
+            }
+        }
+
+        if overlay {
+            let (scx, scy) = (self.position.scx as usize, self.position.scy as usize);
+            outline(
+                &mut image,
+                scx,
+                scy,
+                SCREEN_WIDTH,
+                SCREEN_HEIGHT,
+                VIEWPORT_COLOR,
+            );
+
+            // The window is drawn from the top left of its map, over the rest of
+            // the screen from WX - 7, WY.
+            let wx = self.position.wx as usize;
+            let wy = self.position.wy as usize;
+            let shown = self.lcdc.window_enabled(&self.emu_mode)
+                && self.lcdc.win_tilemap() == map.addr()
+                && wx < SCREEN_WIDTH + 7
+                && wy < SCREEN_HEIGHT;
+
+            if shown {
+                let left = wx.saturating_sub(7);
+                outline(
+                    &mut image,
+                    0,
+                    0,
+                    SCREEN_WIDTH - left,
+                    SCREEN_HEIGHT - wy,
+                    WINDOW_COLOR,
+                );
+            }
+        }
+
+        image
+    }
+
+    /// All of OAM, with which lines picked each entry.
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        let height = self.obj_height();
+
+        (0..40)
+            .map(|index| {
+                let sprite = self.oam_sprite(index);
+                let mut selected_lines = Vec::new();
+                let mut dropped_lines = Vec::new();
+
+                for line in 0..SCREEN_HEIGHT {
+                    let picked = self.oam_lines[line];
+                    let y = line + 16;
+                    let on_line = y >= sprite.y as usize && y < sprite.y as usize + height;
+
+                    if picked & 1 << index != 0 {
+                        selected_lines.push(line as u8);
+                    } else if on_line && picked.count_ones() == 10 {
+                        dropped_lines.push(line as u8);
+                    }
+                }
+
+                OamEntry {
+                    index,
+                    sprite,
+                    selected_lines,
+                    dropped_lines,
+                    thumbnail: self.thumbnail(&sprite),
+                }
+            })
+            .collect()
+    }
+
+    /// How many objects the OAM search of each line picked, at most 10.
+    pub fn sprites_per_line(&self) -> Vec<u8> {
+        self.oam_lines
+            .iter()
+            .map(|picked| picked.count_ones() as u8)
+            .collect()
+    }
+
+    pub fn obj_height(&self) -> usize {
+        if self.lcdc.obj_size == 0 {
+            8
+        } else {
+            16
+        }
+    }
+
+    /// The object in OAM entry `index` (0-39) as RGBA, 8 pixels wide and
+    /// `obj_height` high. `None` for any other index.
+    pub fn sprite_thumbnail(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= 40 {
+            return None;
+        }
+        Some(self.thumbnail(&self.oam_sprite(index)))
+    }
+
+    fn oam_sprite(&self, index: usize) -> Sprite {
+        Sprite::from(&self.oam[index * 4..(index + 1) * 4])
+    }
+
+    fn thumbnail(&self, sprite: &Sprite) -> Vec<u8> {
+        let height = self.obj_height();
+        let (palette, bank) = match self.emu_mode {
+            EmulationMode::Dmg if sprite.obp1 => (TilePalette::Obp1, 0),
+            EmulationMode::Dmg => (TilePalette::Obp0, 0),
+            EmulationMode::Cgb => (
+                TilePalette::CgbObj(sprite.obp_num as usize),
+                sprite.vram_bank,
+            ),
+        };
+        let colors = self.shades(palette);
+        let mut image = vec![0; 8 * height * DEPTH];
+
+        for row in 0..height {
+            let src_row = if sprite.mirror_vertical {
+                height - 1 - row
+            } else {
+                row
+            };
+            // 8x16 objects ignore the low bit of the tile number.
+            let tile = match height {
+                8 => sprite.number as usize,
+                _ => (sprite.number & 0xFE) as usize + src_row / 8,
+            };
+            let (lo, hi) = self.tile_row(bank, tile, src_row % 8);
+
+            for col in 0..8 {
+                let src_col = if sprite.mirror_horizontal {
+                    col ^ 0x7
+                } else {
+                    col
+                };
+                let value = tile_pixel(lo, hi, src_col) as usize;
+
+                if value != 0 {
+                    put_pixel(&mut image, 8, col, row, colors[value]);
+                }
+            }
+        }
+
+        image
+    }
+
+    /// The colours of values 0-3, as they'd be shown on screen. `None` if there's
+    /// no such palette.
+    pub fn palette_rgb(&self, palette: TilePalette) -> Option<Shades> {
+        if !palette.exists() {
+            return None;
+        }
+        Some(self.shades(palette))
+    }
+
+    /// What value 0-3 of `palette` is set to: a shade (0-3) in BGP, OBP0 or OBP1,
+    /// a BGR555 colour in CGB palette RAM. `None` if either is out of range.
+    pub fn palette_entry(&self, palette: TilePalette, value: usize) -> Option<u16> {
+        if !palette.exists() || value >= 4 {
+            return None;
+        }
+        Some(self.entry(palette, value))
+    }
+
+    /// Changes one entry, as `palette_entry` reads it, like the game had written it.
+    /// Returns false, changing nothing, if `palette` or `value` is out of range.
+    pub fn set_palette_entry(&mut self, palette: TilePalette, value: usize, entry: u16) -> bool {
+        if !palette.exists() || value >= 4 {
+            return false;
+        }
+
+        let dmg = |register: &mut u8| {
+            *register &= !(0x3 << (2 * value));
+            *register |= (entry as u8 & 0x3) << (2 * value);
+        };
+
+        match palette {
+            TilePalette::Bgp => dmg(&mut self.dmgp.bgp),
+            TilePalette::Obp0 => dmg(&mut self.dmgp.obp0),
+            TilePalette::Obp1 => dmg(&mut self.dmgp.obp1),
+            TilePalette::CgbBg(n) => set_cgb_color(&mut self.bgp_ram, n, value, entry),
+            TilePalette::CgbObj(n) => set_cgb_color(&mut self.obp_ram, n, value, entry),
+        }
+        true
+    }
+
+    // `palette_rgb` for a palette known to exist.
+    fn shades(&self, palette: TilePalette) -> Shades {
+        let mut colors = [(0, 0, 0); 4];
+
+        for (value, color) in colors.iter_mut().enumerate() {
+            let entry = self.entry(palette, value);
+
+            *color = match palette {
+                TilePalette::Bgp => self.dmg_rgb(entry as usize, None),
+                TilePalette::Obp0 => self.dmg_rgb(entry as usize, Some(0)),
+                TilePalette::Obp1 => self.dmg_rgb(entry as usize, Some(1)),
+                TilePalette::CgbBg(_) | TilePalette::CgbObj(_) => self.color_lut.get(entry),
+            };
+        }
+
+        colors
+    }
+
+    // `palette_entry` for a palette and value known to exist.
+    fn entry(&self, palette: TilePalette, value: usize) -> u16 {
+        let dmg = |register: u8| (register >> (2 * value) & 0x3) as u16;
+
+        match palette {
+            TilePalette::Bgp => dmg(self.dmgp.bgp),
+            TilePalette::Obp0 => dmg(self.dmgp.obp0),
+            TilePalette::Obp1 => dmg(self.dmgp.obp1),
+            TilePalette::CgbBg(n) => cgb_color(&self.bgp_ram, n, value),
+            TilePalette::CgbObj(n) => cgb_color(&self.obp_ram, n, value),
+        }
+    }
+
+    // The two bytes of one row of a tile, numbered from $8000.
+    fn tile_row(&self, bank: usize, tile: usize, row: usize) -> (u8, u8) {
+        let vram = if bank == 0 { &self.vram0 } else { &self.vram1 };
+        let addr = tile * 16 + row * 2;
+        (vram[addr], vram[addr + 1])
+    }
+}
+
+fn cgb_color(palette_ram: &[u8], palette: usize, value: usize) -> u16 {
+    let i = palette * 8 + value * 2;
+    (palette_ram[i + 1] as u16) << 8 | palette_ram[i] as u16
+}
+
+fn set_cgb_color(palette_ram: &mut [u8], palette: usize, value: usize, color: u16) {
+    let i = palette * 8 + value * 2;
+    palette_ram[i] = color as u8;
+    palette_ram[i + 1] = (color >> 8) as u8 & 0x7F;
+}
+
+// Value of pixel `col` (0 is leftmost) in a row.
+fn tile_pixel(lo: u8, hi: u8, col: usize) -> u8 {
+    let bit = 7 - col;
+    (hi >> bit & 1) << 1 | (lo >> bit & 1)
+}
+
+// A rectangle on the tilemap, wrapping around its edges like the viewport does.
+fn outline(image: &mut [u8], x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+    let mut plot = |dx: usize, dy: usize| {
+        put_pixel(
+            image,
+            TILEMAP_SIZE,
+            (x + dx) % TILEMAP_SIZE,
+            (y + dy) % TILEMAP_SIZE,
+            color,
+        );
+    };
+
+    for dx in 0..width {
+        plot(dx, 0);
+        plot(dx, height - 1);
+    }
+    for dy in 0..height {
+        plot(0, dy);
+        plot(width - 1, dy);
+    }
+}
+
+fn put_pixel(image: &mut [u8], width: usize, x: usize, y: usize, (r, g, b): Rgb) {
+    let i = (y * width + x) * DEPTH;
+    image[i..i + DEPTH].copy_from_slice(&[r, g, b, 255]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::colorize::CompatPalette;
+    use crate::gpu::palette::{DmgPalette, DmgPreset};
+    use crate::gpu::GpuMode;
+
+    fn pixel(image: &[u8], width: usize, x: usize, y: usize) -> Rgb {
+        let i = (y * width + x) * DEPTH;
+        (image[i], image[i + 1], image[i + 2])
+    }
+
+    #[test]
+    fn test_tile_data_image() {
+        let mut gpu = Gpu::new(EmulationMode::Cgb);
+        gpu.set_dmg_palette(DmgPalette::from(DmgPreset::Greyscale));
+        gpu.dmgp.bgp = 0xE4;
+        gpu.dmgp.obp0 = 0x1B;
+
+        // Tile 1, first row: value 1, then value 2 in the last pixel.
+        gpu.vram0[0x10] = 0xFE;
+        gpu.vram0[0x11] = 0x01;
+        // Bank 1, tile 383, last row: value 3.
+        gpu.vram1[0x17FE] = 0xFF;
+        gpu.vram1[0x17FF] = 0xFF;
+        // CGB background palette 2, value 1 is red.
+        gpu.bgp_ram[2 * 8 + 2] = 0x1F;
+
+        let image = gpu.tile_data_image(TilePalette::Bgp).unwrap();
+        assert_eq!(image.len(), TILE_DATA_WIDTH * TILE_DATA_HEIGHT * 4);
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 0, 0), (255, 255, 255));
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 8, 0), (170, 170, 170));
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 15, 0), (85, 85, 85));
+        assert_eq!(
+            pixel(
+                &image,
+                TILE_DATA_WIDTH,
+                TILE_DATA_WIDTH - 1,
+                TILE_DATA_HEIGHT - 1
+            ),
+            (0, 0, 0)
+        );
+
+        let image = gpu.tile_data_image(TilePalette::Obp0).unwrap();
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 0, 0), (0, 0, 0));
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 8, 0), (85, 85, 85));
+
+        let image = gpu.tile_data_image(TilePalette::CgbBg(2)).unwrap();
+        assert_eq!(gpu.tile_data_image(TilePalette::CgbBg(8)), None);
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 8, 0), (255, 0, 0));
+        assert_eq!(pixel(&image, TILE_DATA_WIDTH, 0, 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_tilemap_image() {
+        let mut gpu = Gpu::new(EmulationMode::Cgb);
+
+        // Tile $80 (at $8800 with signed addressing), value 1 on its first row
+        // except the last pixel.
+        gpu.vram0[0x0800] = 0xFE;
+        // The same tile from bank 1 has value 2 in its last pixel.
+        gpu.vram1[0x0801] = 0x01;
+        // CGB background palette 1: value 1 red, value 2 green.
+        gpu.bgp_ram[8 + 2] = 0x1F;
+        gpu.bgp_ram[8 + 4] = 0xE0;
+        gpu.bgp_ram[8 + 5] = 0x03;
+
+        // $9C00 row 1, column 2: tile $80, palette 1. Next to it flipped both ways
+        // from bank 1, and drawn over objects.
+        gpu.vram0[0x1C22] = 0x80;
+        gpu.vram1[0x1C22] = 0x01;
+        gpu.vram0[0x1C23] = 0x80;
+        gpu.vram1[0x1C23] = 0x80 | 0x40 | 0x20 | 0x08 | 0x01;
+
+        let image = gpu.tilemap_image(TileMap::High, false);
+        assert_eq!(image.len(), TILEMAP_SIZE * TILEMAP_SIZE * 4);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 16, 8), (255, 0, 0));
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 23, 8), (0, 0, 0));
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 24, 15), (0, 255, 0));
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 24, 8), (0, 0, 0));
+
+        // Unsigned addressing reads tile $80 from $8800 too.
+        gpu.lcdc.tiledata_sel = 0x10;
+        assert_eq!(gpu.tilemap_image(TileMap::High, false), image);
+
+        gpu.position.scx = 200;
+        gpu.position.scy = 10;
+        gpu.position.wx = 87;
+        gpu.position.wy = 100;
+        gpu.lcdc.win_display_enable = 0x20;
+
+        let image = gpu.tilemap_image(TileMap::High, true);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 24, 8), PRIORITY_COLOR);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 16, 8), (255, 0, 0));
+        // The viewport wraps around the right edge.
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 200, 10), VIEWPORT_COLOR);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 250, 153), VIEWPORT_COLOR);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 103, 20), VIEWPORT_COLOR);
+        // The window uses $9800.
+        assert_ne!(pixel(&image, TILEMAP_SIZE, 0, 0), WINDOW_COLOR);
+        let low = gpu.tilemap_image(TileMap::Low, true);
+        assert_eq!(pixel(&low, TILEMAP_SIZE, 0, 0), WINDOW_COLOR);
+
+        // 80x44 from the top left of its own map.
+        gpu.lcdc.win_tilemap_sel = 0x40;
+        let image = gpu.tilemap_image(TileMap::High, true);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 0, 0), WINDOW_COLOR);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 79, 20), WINDOW_COLOR);
+        assert_eq!(pixel(&image, TILEMAP_SIZE, 40, 43), WINDOW_COLOR);
+        assert_ne!(pixel(&image, TILEMAP_SIZE, 80, 20), WINDOW_COLOR);
+        assert_ne!(pixel(&image, TILEMAP_SIZE, 40, 44), WINDOW_COLOR);
+    }
+
+    #[test]
+    fn test_palette_entries() {
+        let mut gpu = Gpu::new(EmulationMode::Cgb);
+        gpu.set_dmg_palette(DmgPalette::from(DmgPreset::Greyscale));
+        gpu.dmgp.obp1 = 0xE4;
+
+        assert!(gpu.set_palette_entry(TilePalette::Obp1, 2, 0));
+        assert_eq!(gpu.dmgp.obp1, 0xC4);
+        assert_eq!(gpu.palette_entry(TilePalette::Obp1, 3), Some(3));
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::Obp1),
+            Some([(255, 255, 255), (170, 170, 170), (255, 255, 255), (0, 0, 0)])
+        );
+
+        assert!(gpu.set_palette_entry(TilePalette::CgbObj(7), 3, 0xFC00));
+        assert_eq!(&gpu.obp_ram[0x3E..], &[0x00, 0x7C]);
+        assert_eq!(gpu.palette_entry(TilePalette::CgbObj(7), 3), Some(0x7C00));
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::CgbObj(7)).unwrap()[3],
+            (0, 0, 255)
+        );
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::CgbBg(7)).unwrap()[3],
+            (0, 0, 0)
+        );
+
+        // Out of range palettes and values change nothing.
+        let ram = gpu.obp_ram.clone();
+        assert!(!gpu.set_palette_entry(TilePalette::CgbObj(8), 0, 0x7FFF));
+        assert!(!gpu.set_palette_entry(TilePalette::Obp0, 4, 3));
+        assert_eq!(gpu.obp_ram, ram);
+        assert_eq!(gpu.palette_entry(TilePalette::CgbBg(8), 0), None);
+        assert_eq!(gpu.palette_entry(TilePalette::Bgp, 4), None);
+        assert_eq!(gpu.palette_rgb(TilePalette::CgbObj(8)), None);
+    }
+
+    #[test]
+    fn test_compat_palette() {
+        let mut gpu = Gpu::new(EmulationMode::Dmg);
+        gpu.dmgp.bgp = 0xE4;
+        gpu.dmgp.obp1 = 0x1B;
+
+        gpu.set_compat_palette(CompatPalette {
+            bg: [0x7FFF, 0x001F, 0x03E0, 0x7C00],
+            obj0: [0x0000; 4],
+            obj1: [0x0000, 0x0000, 0x0000, 0x7FFF],
+        });
+        assert_eq!(gpu.palette_entry(TilePalette::CgbBg(0), 1), Some(0x001F));
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::Bgp),
+            Some([(255, 255, 255), (255, 0, 0), (0, 255, 0), (0, 0, 255)])
+        );
+        // OBP1 maps value 0 to shade 3.
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::Obp1).unwrap()[0],
+            (255, 255, 255)
+        );
+
+        gpu.set_dmg_palette(DmgPalette::from(DmgPreset::Greyscale));
+        assert_eq!(
+            gpu.palette_rgb(TilePalette::Bgp).unwrap()[1],
+            (170, 170, 170)
+        );
+
+        // CGB games keep their own palettes.
+        let mut cgb = Gpu::new(EmulationMode::Cgb);
+        cgb.set_compat_palette(CompatPalette {
+            bg: [0x7FFF; 4],
+            obj0: [0x7FFF; 4],
+            obj1: [0x7FFF; 4],
+        });
+        assert_eq!(cgb.palette_entry(TilePalette::CgbBg(0), 0), Some(0));
+    }
+
+    fn render(hidden: HiddenLayers) -> Gpu {
+        let mut gpu = Gpu::new(EmulationMode::Dmg);
+        gpu.set_dmg_palette(DmgPalette::from(DmgPreset::Greyscale));
+        gpu.set_hidden_layers(hidden);
+
+        // Tile 1 is all value 3, at the top left of the background and window.
+        for i in 0x10..0x20 {
+            gpu.vram0[i] = 0xFF;
+        }
+        gpu.vram0[0x1800] = 1;
+        gpu.vram0[0x1C00] = 1;
+        // Object 0 is tile 1 at (8, 0) on top of a blank bit of background,
+        // object 1 is under it. Object 2 is behind the background at (0, 1).
+        gpu.oam[0..4].copy_from_slice(&[16, 16, 1, 0x00]);
+        gpu.oam[4..8].copy_from_slice(&[16, 16, 1, 0x10]);
+        gpu.oam[8..12].copy_from_slice(&[17, 8, 1, 0x80]);
+
+        gpu.set_byte(0xFF47, 0xE4);
+        gpu.set_byte(0xFF48, 0x40);
+        gpu.set_byte(0xFF49, 0x80);
+        gpu.set_byte(0xFF4A, 72);
+        gpu.set_byte(0xFF4B, 7);
+        // Display, window from $9C00, window, tiles from $8000, objects and
+        // background.
+        gpu.set_byte(0xFF40, 0xF3);
+        gpu.tick(2 * 70224);
+
+        gpu
+    }
+
+    fn screen_pixel(gpu: &Gpu, x: usize, y: usize) -> Rgb {
+        pixel(&gpu.lcd, SCREEN_WIDTH, x, y)
+    }
+
+    #[test]
+    fn test_hidden_layers() {
+        let (black, dark, light, white) =
+            ((0, 0, 0), (85, 85, 85), (170, 170, 170), (255, 255, 255));
+
+        let gpu = render(HiddenLayers::default());
+        assert_eq!(screen_pixel(&gpu, 0, 0), black);
+        assert_eq!(screen_pixel(&gpu, 8, 0), light);
+        assert_eq!(screen_pixel(&gpu, 0, 72), black);
+        let mode3_clocks = gpu.mode3_clocks;
+
+        let gpu = render(HiddenLayers {
+            background: true,
+            ..HiddenLayers::default()
+        });
+        assert_eq!(screen_pixel(&gpu, 0, 0), white);
+        assert_eq!(screen_pixel(&gpu, 0, 1), light);
+        assert_eq!(screen_pixel(&gpu, 8, 0), light);
+        assert_eq!(screen_pixel(&gpu, 0, 72), black);
+        assert_eq!(gpu.mode3_clocks, mode3_clocks);
+
+        let gpu = render(HiddenLayers {
+            window: true,
+            ..HiddenLayers::default()
+        });
+        assert_eq!(screen_pixel(&gpu, 0, 0), black);
+        assert_eq!(screen_pixel(&gpu, 0, 72), white);
+
+        let gpu = render(HiddenLayers {
+            oam_entries: 1,
+            ..HiddenLayers::default()
+        });
+        assert_eq!(screen_pixel(&gpu, 8, 0), dark);
+        assert_eq!(gpu.mode3_clocks, mode3_clocks);
+
+        let gpu = render(HiddenLayers {
+            objects: true,
+            ..HiddenLayers::default()
+        });
+        assert_eq!(screen_pixel(&gpu, 8, 0), white);
+    }
+
+    #[test]
+    fn test_oam_entries() {
+        let mut gpu = Gpu::new(EmulationMode::Dmg);
+        gpu.set_dmg_palette(DmgPalette::from(DmgPreset::Greyscale));
+        gpu.dmgp.obp1 = 0xE4;
+
+        // 12 objects on line 0, the last two don't fit.
+        for i in 0..12 {
+            gpu.oam[i * 4..i * 4 + 4].copy_from_slice(&[16, 8 + i as u8, 0, 0]);
+        }
+        // 8x16 from tile 5, which is tile 4 on top and tile 5 below, flipped
+        // horizontally with OBP1.
+        gpu.oam[12 * 4..12 * 4 + 4].copy_from_slice(&[40, 8, 5, 0x30]);
+        gpu.vram0[4 * 16] = 0x80;
+        gpu.vram0[5 * 16 + 1] = 0x80;
+
+        gpu.lcdc.obj_size = 0x04;
+        for line in 0..SCREEN_HEIGHT as u8 {
+            gpu.position.ly = line;
+            gpu.stat.mode = GpuMode::OamSearch;
+            gpu.clock = 0;
+            gpu.search_idx = 0;
+            gpu.comparators.clear();
+            gpu.locations.clear();
+            gpu.run_oam_search(80);
+        }
+
+        let entries = gpu.oam_entries();
+        assert_eq!(entries.len(), 40);
+        assert_eq!(entries[0].selected_lines, (0..16).collect::<Vec<u8>>());
+        assert_eq!(entries[11].selected_lines, Vec::<u8>::new());
+        assert_eq!(entries[11].dropped_lines, (0..16).collect::<Vec<u8>>());
+        assert_eq!(entries[12].selected_lines, (24..40).collect::<Vec<u8>>());
+        assert!(entries[12].dropped_lines.is_empty());
+        assert_eq!(gpu.sprites_per_line()[0], 10);
+        assert_eq!(gpu.sprites_per_line()[30], 1);
+
+        let thumbnail = &entries[12].thumbnail;
+        assert_eq!(thumbnail.len(), 8 * 16 * 4);
+        assert_eq!(pixel(thumbnail, 8, 7, 0), (170, 170, 170));
+        assert_eq!(pixel(thumbnail, 8, 7, 8), (85, 85, 85));
+        // Colour 0 is transparent.
+        assert_eq!(thumbnail[3], 0);
+        assert_eq!(gpu.sprite_thumbnail(12).as_ref(), Some(thumbnail));
+        assert_eq!(gpu.sprite_thumbnail(40), None);
+        assert_eq!(
+            entries[12].to_string(),
+            "#12 X=  8 Y= 40 tile=05 OBP1 CGB=0 bank=0 flip=X- above lines=16 dropped=0"
+        );
+    }
+}
@@ -8,10 +8,20 @@
 // in sameboy_pixel_pipeline.md. I also consulted LIJI and got advice/help from him and
 // several others.
 
+pub mod blend;
+pub mod color_correction;
+pub mod colorize;
+pub mod debug;
+pub mod palette;
 pub mod registers;
 pub mod tiles;
 
 use crate::cpu::EmulationMode;
+use crate::gpu::blend::{FrameBlender, FrameBlending};
+use crate::gpu::color_correction::{ColorCorrection, ColorLut};
+use crate::gpu::colorize::CompatPalette;
+use crate::gpu::debug::HiddenLayers;
+use crate::gpu::palette::DmgPalette;
 use crate::gpu::registers::{ColorPalette, LcdControl, LcdPosition, LcdStatus, MonochromePalette};
 use crate::gpu::tiles::Sprite;
 use std::collections::VecDeque;
@@ -62,6 +72,7 @@ pub struct PixelFifoItem {
     pub obj_to_bg_prio: u8,
     pub obj_to_obj_prio: u8,
     pub bg_to_oam_prio: u8,
+    pub window: bool,
 }
 
 pub struct BgFifo {
@@ -82,6 +93,7 @@ impl BgFifo {
         palette_num: u8,
         flip_x: bool,
         bg_to_oam_prio: u8,
+        window: bool,
     ) {
         if flip_x {
             low = low.reverse_bits();
@@ -97,6 +109,7 @@ impl BgFifo {
                 obj_to_bg_prio: 0,
                 obj_to_obj_prio: 0,
                 bg_to_oam_prio,
+                window,
             });
 
             low <<= 1;
@@ -158,6 +171,7 @@ impl ObjFifo {
                     obj_to_bg_prio,
                     obj_to_obj_prio,
                     bg_to_oam_prio: 0,
+                    window: false,
                 };
             }
 
@@ -259,9 +273,15 @@ pub struct Gpu {
     pub obp_ram: Vec<u8>,
     pub oam: Vec<u8>,
     cgbp: ColorPalette,
+    color_lut: ColorLut,
+    blender: FrameBlender,
+    hidden: HiddenLayers,
     emu_mode: EmulationMode,
     lcdc: LcdControl,
     dmgp: MonochromePalette,
+    dmg_palette: DmgPalette,
+    // DMG shades go through CGB palette RAM, like a DMG game on a CGB.
+    compat: bool,
     position: LcdPosition,
     stat: LcdStatus,
     clock: usize,
@@ -285,6 +305,8 @@ pub struct Gpu {
     locations: Vec<usize>,
     // sprites: Vec<Sprite>,
     search_idx: usize,
+    // For each line, a bit per OAM entry picked by its OAM search.
+    oam_lines: Vec<u64>,
 
     sprite_i: usize,
     in_sprite_fetch: bool,
@@ -316,9 +338,14 @@ impl Gpu {
             obp_ram: vec![0; PALETTE_RAM_SIZE],
             oam: vec![0; OAM_SIZE],
             cgbp: ColorPalette::default(),
+            color_lut: ColorLut::new(ColorCorrection::default()),
+            blender: FrameBlender::new(FrameBlending::default()),
+            hidden: HiddenLayers::default(),
             emu_mode,
             lcdc: LcdControl::default(),
             dmgp: MonochromePalette::default(),
+            dmg_palette: DmgPalette::default(),
+            compat: false,
             position: LcdPosition::default(),
             stat: LcdStatus::default(),
             clock: 0,
@@ -343,6 +370,7 @@ impl Gpu {
             locations: Vec::with_capacity(10),
             // sprites: Vec::with_capacity(40),
             search_idx: 0,
+            oam_lines: vec![0; SCREEN_HEIGHT],
 
             sprite_i: 0,
             in_sprite_fetch: false,
@@ -365,10 +393,79 @@ impl Gpu {
         }
     }
 
+    /// The colours DMG shades are drawn in, from the next pixel on.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+        self.compat = false;
+    }
+
+    /// Colours a DMG game with a CGB compatibility palette: `palette` goes in BG
+    /// palette 0 and OBJ palettes 0 and 1, and BGP, OBP0 and OBP1 pick colours from
+    /// there until the next `set_dmg_palette`. Has no effect on CGB games.
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        if self.emu_mode != EmulationMode::Dmg {
+            return;
+        }
+
+        let write = |ram: &mut [u8], colors: [u16; 4]| {
+            for (bytes, color) in ram.chunks_exact_mut(2).zip(colors.iter()) {
+                bytes[0] = *color as u8;
+                bytes[1] = (*color >> 8) as u8 & 0x7F;
+            }
+        };
+        write(&mut self.bgp_ram[0..8], palette.bg);
+        write(&mut self.obp_ram[0..8], palette.obj0);
+        write(&mut self.obp_ram[8..16], palette.obj1);
+
+        self.compat = true;
+    }
+
+    pub fn dmg_palette(&self) -> &DmgPalette {
+        &self.dmg_palette
+    }
+
+    /// How CGB colours are converted, from the next pixel on.
+    pub fn set_color_correction(&mut self, mode: ColorCorrection) {
+        if mode != self.color_lut.mode() {
+            self.color_lut = ColorLut::new(mode);
+        }
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_lut.mode()
+    }
+
+    /// LCD ghosting, applied to each frame once it's complete.
+    pub fn set_frame_blending(&mut self, mode: FrameBlending) {
+        if mode != self.blender.mode() {
+            self.blender = FrameBlender::new(mode);
+        }
+    }
+
+    pub fn frame_blending(&self) -> FrameBlending {
+        self.blender.mode()
+    }
+
+    pub fn set_hidden_layers(&mut self, hidden: HiddenLayers) {
+        self.hidden = hidden;
+    }
+
+    pub fn hidden_layers(&self) -> HiddenLayers {
+        self.hidden
+    }
+
     pub fn mode(&self) -> &GpuMode {
         &self.stat.mode
     }
 
+    pub fn ly(&self) -> u8 {
+        self.position.ly
+    }
+
+    pub fn display_enabled(&self) -> bool {
+        self.lcdc.display_enabled()
+    }
+
     pub fn screen(&self) -> *const u8 {
         self.lcd.as_ptr()
     }
@@ -397,6 +494,27 @@ impl Gpu {
         }
     }
 
+    /// Clocks the gpu can be left behind for. Only the middle of HBlank and VBlank
+    /// can be skipped over, everything else is ticked as it happens.
+    pub fn cycles_until_event(&self) -> usize {
+        if !self.lcdc.display_enabled() {
+            return usize::MAX;
+        }
+
+        if self.first_line0 || self.stat_int_update_pending || self.stat.mode != self.next_mode {
+            return 0;
+        }
+
+        match self.stat.mode {
+            GpuMode::HBlank => {
+                let hblank_clocks = CYCLES_IN_LINE - (self.mode2_clocks + self.mode3_clocks);
+                hblank_clocks.saturating_sub(self.clock)
+            }
+            GpuMode::VBlank => 456usize.saturating_sub(self.clock),
+            _ => 0,
+        }
+    }
+
     fn line0_tick(&mut self, mut cycles: usize) -> usize {
         if self.line0_clocks == 0 {
             if self.position.wy == 0 {
@@ -442,6 +560,10 @@ impl Gpu {
 
             // self.sprites.push(sprite);
 
+            if self.search_idx == 0 {
+                self.oam_lines[self.position.ly as usize] = 0;
+            }
+
             if self.comparators.len() < 10 {
                 let sprite =
                     Sprite::from(&self.oam[self.search_idx * 4..(self.search_idx + 1) * 4]);
@@ -481,6 +603,7 @@ impl Gpu {
 
         self.comparators.insert(i, x);
         self.locations.insert(i, self.search_idx);
+        self.oam_lines[self.position.ly as usize] |= 1 << self.search_idx;
     }
 
     fn run_init_pixel_transfer(&mut self, cycles: usize) -> usize {
@@ -629,8 +752,14 @@ impl Gpu {
                     EmulationMode::Cgb => sprite.vram_bank,
                 };
 
-                let low = self.get_vram_byte(addr, bank);
-                let high = self.get_vram_byte(addr + 1, bank);
+                let mut low = self.get_vram_byte(addr, bank);
+                let mut high = self.get_vram_byte(addr + 1, bank);
+
+                // Still fetched, so hiding it doesn't change timing.
+                if self.hidden.oam_entries & 1 << i != 0 {
+                    low = 0;
+                    high = 0;
+                }
 
                 let palette_num = match self.emu_mode {
                     EmulationMode::Dmg => sprite.obp1 as u8,
@@ -692,7 +821,7 @@ impl Gpu {
 
             let spx = match self.obj_fifo.pop() {
                 Some(spx) => {
-                    if spx.value > 0 && self.lcdc.obj_display_enable != 0 {
+                    if spx.value > 0 && self.lcdc.obj_display_enable != 0 && !self.hidden.objects {
                         draw_sprite = true;
                         bg_over_sprite |= spx.obj_to_bg_prio;
                     }
@@ -716,6 +845,15 @@ impl Gpu {
                 }
             }
 
+            let bg_hidden = if px.window {
+                self.hidden.window
+            } else {
+                self.hidden.background
+            };
+            if bg_hidden {
+                value = 0;
+            }
+
             if value != 0 && bg_over_sprite != 0 {
                 draw_sprite = false;
             }
@@ -724,12 +862,14 @@ impl Gpu {
                 EmulationMode::Dmg => self.dmgp.bgp as u16,
                 EmulationMode::Cgb => self.cgb_bg_palette(px, value),
             };
+            let mut obj = None;
 
             if draw_sprite {
                 value = spx.value;
 
                 palette = match self.emu_mode {
                     EmulationMode::Dmg => {
+                        obj = Some(spx.palette_num);
                         if spx.palette_num == 0 {
                             self.dmgp.obp0 as u16
                         } else {
@@ -740,7 +880,11 @@ impl Gpu {
                 }
             }
 
-            let (r, g, b) = self.get_rgb(value, palette);
+            let (r, g, b) = if bg_hidden && !draw_sprite {
+                self.blank_rgb()
+            } else {
+                self.get_rgb(value, palette, obj)
+            };
             self.write_lcd(r, g, b);
 
             self.lx += 1;
@@ -833,6 +977,7 @@ impl Gpu {
                         self.fetcher.current_tile_attr & 0x7,
                         self.fetcher.current_tile_attr & 0x20 != 0,
                         self.fetcher.current_tile_attr & 0x80,
+                        self.wx_triggered,
                     );
 
                     self.fetcher.state = FetcherState::Sleep0;
@@ -850,6 +995,7 @@ impl Gpu {
                         self.fetcher.current_tile_attr & 0x7,
                         self.fetcher.current_tile_attr & 0x20 != 0,
                         self.fetcher.current_tile_attr & 0x80,
+                        self.wx_triggered,
                     );
 
                     self.fetcher.state = FetcherState::Sleep0;
@@ -863,6 +1009,7 @@ impl Gpu {
                         self.fetcher.current_tile_attr & 0x7,
                         self.fetcher.current_tile_attr & 0x20 != 0,
                         self.fetcher.current_tile_attr & 0x80,
+                        self.wx_triggered,
                     );
 
                     self.fetcher.advance_state();
@@ -910,6 +1057,7 @@ impl Gpu {
                 self.next_mode = GpuMode::VBlank;
                 // self.request_vblank_interrupt();
                 self.vblank_event = true;
+                self.blender.apply(&mut self.lcd);
             } else {
                 self.next_mode = GpuMode::OamSearch;
             }
@@ -989,7 +1137,7 @@ impl Gpu {
                 // further offset for scroll x
                 self.lx -= (self.position.scx % 8) as i16;
                 // push 8 'junk' pixels to fifo
-                self.bg_fifo.push_row(0, 0, 0, false, 0);
+                self.bg_fifo.push_row(0, 0, 0, false, 0, false);
                 // reset fetcher
                 self.fetcher.x = 0;
                 self.fetcher.state = FetcherState::Sleep0;
@@ -1044,25 +1192,37 @@ impl Gpu {
         self.request_lcd_int = true;
     }
 
-    fn get_rgb(&self, value: u8, palette: u16) -> (u8, u8, u8) {
+    // `obj` is the DMG OBJ palette number, `None` for the background.
+    fn get_rgb(&self, value: u8, palette: u16, obj: Option<u8>) -> (u8, u8, u8) {
         match self.emu_mode {
-            EmulationMode::Dmg => match (palette >> (2 * value)) & 0x3 {
-                0 => (224, 247, 208),
-                1 => (136, 192, 112),
-                2 => (52, 104, 86),
-                _ => (8, 23, 33),
-            },
-            EmulationMode::Cgb => {
-                let r = (palette & 0x001F) >> 0;
-                let g = (palette & 0x03E0) >> 5;
-                let b = (palette & 0x7C00) >> 10;
-
-                (
-                    ((r << 3) | (r >> 2)) as u8,
-                    ((g << 3) | (g >> 2)) as u8,
-                    ((b << 3) | (b >> 2)) as u8,
-                )
-            }
+            EmulationMode::Dmg => self.dmg_rgb(((palette >> (2 * value)) & 0x3) as usize, obj),
+            EmulationMode::Cgb => self.color_lut.get(palette),
+        }
+    }
+
+    // The colour of a DMG shade on the background or OBJ palette `obj`.
+    fn dmg_rgb(&self, shade: usize, obj: Option<u8>) -> (u8, u8, u8) {
+        if self.compat {
+            let (ram, palette) = match obj {
+                None => (&self.bgp_ram, 0),
+                Some(n) => (&self.obp_ram, n as usize),
+            };
+            let i = palette * 8 + shade * 2;
+            return self.color_lut.get((ram[i + 1] as u16) << 8 | ram[i] as u16);
+        }
+
+        match obj {
+            None => self.dmg_palette.bg[shade],
+            Some(0) => self.dmg_palette.obj0[shade],
+            Some(_) => self.dmg_palette.obj1[shade],
+        }
+    }
+
+    // What shows where a hidden layer would be.
+    fn blank_rgb(&self) -> (u8, u8, u8) {
+        match self.emu_mode {
+            EmulationMode::Dmg => self.dmg_rgb(0, None),
+            EmulationMode::Cgb => (255, 255, 255),
         }
     }
 
@@ -1280,6 +1440,7 @@ impl Gpu {
         self.locations.clear();
         self.search_idx = 0;
         self.mode2_clocks = 0;
+        self.oam_lines[0] = 0;
 
         self.mode3_clocks = 0;
 
@@ -0,0 +1,134 @@
+// Colours for DMG games. The hardware only has four shades per palette register, the
+// colour they show up as depends on the screen, so the background, OBP0 and OBP1
+// each get their own four colours (lightest first) which the front end can change
+// at any time.
+//
+// Community palettes are from https://lospec.com/palette-list.
+
+pub type Rgb = (u8, u8, u8);
+
+/// Four colours, from shade 0 (lightest on real hardware) to shade 3.
+pub type Shades = [Rgb; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DmgPalette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+impl DmgPalette {
+    /// The same colours for every layer.
+    pub fn uniform(shades: Shades) -> Self {
+        Self {
+            bg: shades,
+            obj0: shades,
+            obj1: shades,
+        }
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DmgPalette::from(DmgPreset::Green)
+    }
+}
+
+impl From<DmgPreset> for DmgPalette {
+    fn from(preset: DmgPreset) -> Self {
+        DmgPalette::uniform(preset.shades())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DmgPreset {
+    /// The original green screen.
+    Green,
+    /// Game Boy Pocket.
+    Pocket,
+    /// Game Boy Light with the backlight on.
+    Light,
+    Greyscale,
+    Kirokaze,
+    IceCream,
+    Mist,
+    Demichrome,
+}
+
+impl DmgPreset {
+    pub const ALL: [DmgPreset; 8] = [
+        DmgPreset::Green,
+        DmgPreset::Pocket,
+        DmgPreset::Light,
+        DmgPreset::Greyscale,
+        DmgPreset::Kirokaze,
+        DmgPreset::IceCream,
+        DmgPreset::Mist,
+        DmgPreset::Demichrome,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DmgPreset::Green => "DMG green",
+            DmgPreset::Pocket => "Pocket grey",
+            DmgPreset::Light => "Light",
+            DmgPreset::Greyscale => "Greyscale",
+            DmgPreset::Kirokaze => "Kirokaze",
+            DmgPreset::IceCream => "Ice cream",
+            DmgPreset::Mist => "Mist",
+            DmgPreset::Demichrome => "2-bit demichrome",
+        }
+    }
+
+    pub fn shades(&self) -> Shades {
+        match self {
+            DmgPreset::Green => [(224, 247, 208), (136, 192, 112), (52, 104, 86), (8, 23, 33)],
+            DmgPreset::Pocket => [(196, 207, 161), (139, 149, 109), (77, 83, 60), (31, 31, 31)],
+            DmgPreset::Light => [(0, 181, 129), (0, 154, 113), (0, 105, 74), (0, 79, 59)],
+            DmgPreset::Greyscale => [(255, 255, 255), (170, 170, 170), (85, 85, 85), (0, 0, 0)],
+            DmgPreset::Kirokaze => [
+                (226, 243, 228),
+                (148, 227, 68),
+                (70, 135, 143),
+                (51, 44, 80),
+            ],
+            DmgPreset::IceCream => [
+                (255, 246, 211),
+                (249, 168, 117),
+                (235, 107, 111),
+                (124, 63, 88),
+            ],
+            DmgPreset::Mist => [(196, 240, 194), (90, 185, 168), (30, 96, 110), (45, 27, 0)],
+            DmgPreset::Demichrome => [
+                (233, 239, 236),
+                (160, 160, 139),
+                (85, 85, 104),
+                (33, 30, 32),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(DmgPalette::default(), DmgPalette::from(DmgPreset::Green));
+
+        for preset in DmgPreset::ALL.iter() {
+            // Every preset goes from light to dark like the hardware.
+            let luma: Vec<u32> = preset
+                .shades()
+                .iter()
+                .map(|&(r, g, b)| 2 * r as u32 + 5 * g as u32 + b as u32)
+                .collect();
+            assert!(
+                luma.windows(2).all(|pair| pair[0] > pair[1]),
+                "{}",
+                preset.name()
+            );
+        }
+    }
+}
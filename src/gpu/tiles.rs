@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Sprite {
     pub y: u8,
     pub x: u8,
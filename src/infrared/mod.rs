@@ -0,0 +1,169 @@
+// CGB infrared port, RP (FF56). Bit 0 turns the LED on, bit 1 reads the sensor
+// (0 while it sees light) but only when both read enable bits (6-7) are set,
+// otherwise it reads 1. Bits 2-5 always read 1. There's no clock or interrupt,
+// games time the pulses themselves, so whatever sits across from us just sees the
+// LED change as it happens.
+//
+// References: https://gbdev.io/pandocs/CGB_Registers.html#ff56--rp-cgb-mode-only-infrared-communications-port
+
+use crate::cpu::EmulationMode;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const LED: u8 = 0x01;
+const SENSOR: u8 = 0x02;
+const READ_ENABLE: u8 = 0xC0;
+
+/// Whatever the IR port is pointed at.
+pub trait InfraredDevice {
+    /// Called whenever our LED turns on or off.
+    fn set_led(&mut self, on: bool);
+
+    /// Whether light is reaching our sensor.
+    fn receiving(&mut self) -> bool;
+}
+
+/// Nothing in sight, the sensor stays dark.
+pub struct NoInfrared;
+
+impl InfraredDevice for NoInfrared {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn receiving(&mut self) -> bool {
+        false
+    }
+}
+
+/// A mirror in front of the port: the sensor sees our own LED.
+#[derive(Default)]
+pub struct InfraredLoopback {
+    led: bool,
+}
+
+impl InfraredDevice for InfraredLoopback {
+    fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+
+    fn receiving(&mut self) -> bool {
+        self.led
+    }
+}
+
+/// One of two IR ports facing each other, each sensor sees the other's LED.
+pub struct InfraredPeer {
+    leds: Rc<RefCell<[bool; 2]>>,
+    side: usize,
+}
+
+impl InfraredPeer {
+    pub fn pair() -> (InfraredPeer, InfraredPeer) {
+        let leds = Rc::new(RefCell::new([false; 2]));
+
+        (
+            InfraredPeer {
+                leds: leds.clone(),
+                side: 0,
+            },
+            InfraredPeer { leds, side: 1 },
+        )
+    }
+}
+
+impl InfraredDevice for InfraredPeer {
+    fn set_led(&mut self, on: bool) {
+        self.leds.borrow_mut()[self.side] = on;
+    }
+
+    fn receiving(&mut self) -> bool {
+        self.leds.borrow()[1 - self.side]
+    }
+}
+
+pub struct Infrared {
+    rp: u8,
+    device: Box<dyn InfraredDevice>,
+    emu_mode: EmulationMode,
+}
+
+impl Infrared {
+    pub fn new(emu_mode: EmulationMode) -> Self {
+        Self {
+            rp: 0,
+            device: Box::new(NoInfrared),
+            emu_mode,
+        }
+    }
+
+    /// Points the port at `device`, returning what was there before.
+    pub fn connect(&mut self, mut device: Box<dyn InfraredDevice>) -> Box<dyn InfraredDevice> {
+        device.set_led(self.rp & LED != 0);
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn get_byte(&mut self) -> u8 {
+        if self.emu_mode == EmulationMode::Dmg {
+            return 0xFF;
+        }
+
+        let dark = self.rp & READ_ENABLE != READ_ENABLE || !self.device.receiving();
+        0x3C | self.rp | if dark { SENSOR } else { 0 }
+    }
+
+    pub fn set_byte(&mut self, value: u8) {
+        if self.emu_mode == EmulationMode::Dmg {
+            return;
+        }
+
+        let led = value & LED != 0;
+        if led != (self.rp & LED != 0) {
+            self.device.set_led(led);
+        }
+        self.rp = value & (READ_ENABLE | LED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_enable() {
+        let mut ir = Infrared::new(EmulationMode::Cgb);
+        ir.connect(Box::new(InfraredLoopback::default()));
+        assert_eq!(ir.get_byte(), 0x3E);
+
+        // The LED is on but the sensor isn't enabled.
+        ir.set_byte(!READ_ENABLE);
+        assert_eq!(ir.get_byte(), 0x3F);
+
+        ir.set_byte(0xFF);
+        assert_eq!(ir.get_byte(), 0xFD);
+        ir.set_byte(READ_ENABLE);
+        assert_eq!(ir.get_byte(), 0xFE);
+    }
+
+    #[test]
+    fn test_peer() {
+        let (a, b) = InfraredPeer::pair();
+        let mut first = Infrared::new(EmulationMode::Cgb);
+        let mut second = Infrared::new(EmulationMode::Cgb);
+        first.connect(Box::new(a));
+        second.connect(Box::new(b));
+
+        first.set_byte(READ_ENABLE);
+        second.set_byte(READ_ENABLE | LED);
+        assert_eq!(first.get_byte() & SENSOR, 0);
+        assert_eq!(second.get_byte() & SENSOR, SENSOR);
+
+        second.set_byte(READ_ENABLE);
+        assert_eq!(first.get_byte() & SENSOR, SENSOR);
+    }
+
+    #[test]
+    fn test_dmg_has_no_port() {
+        let mut ir = Infrared::new(EmulationMode::Dmg);
+        ir.set_byte(0xC1);
+        assert_eq!(ir.get_byte(), 0xFF);
+    }
+}
@@ -1,11 +1,17 @@
 mod apu;
 mod cartridge;
 pub mod cpu;
+#[cfg(feature = "wasm")]
 pub mod emulator;
-mod events;
+pub mod events;
+pub mod filter;
+pub mod gameboy;
 mod gpu;
+pub mod infrared;
 mod joypad;
 mod memory;
+pub mod profiler;
+pub mod serial;
 mod timer;
 mod utils;
 
@@ -0,0 +1,198 @@
+// Everything the cpu sees of the rest of the system. `Mmu` is the real thing; other
+// implementations let the cpu run against flat RAM (`FlatBus`, tests/single_step.rs),
+// stand in for a different system configuration, or wrap another bus to watch the
+// traffic going through it.
+//
+// Only `read`, `write` and `tick` are required. The rest are hooks for the parts of
+// the Game Boy that reach past the memory map (DMA stalls, KEY1 speed switches, IE
+// living outside any register bank) and default to a system that has none of them.
+
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Advances everything on the bus by `cycles` clocks.
+    fn tick(&mut self, cycles: usize);
+
+    /// Advances an active OAM DMA. Only called while the cpu is neither halted
+    /// nor stopped.
+    fn dma_tick(&mut self, _cycles: usize) {}
+
+    /// Runs a pending general purpose or HBlank DMA block, returning the number of
+    /// clocks the cpu is stalled for.
+    fn hdma_transfer(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// The IE register.
+    fn interrupt_enable(&mut self) -> u8 {
+        self.read(0xFFFF)
+    }
+
+    /// Interrupts that are both requested (IF) and enabled (IE).
+    fn pending_interrupts(&mut self) -> u8 {
+        self.interrupt_enable() & self.read(0xFF0F) & 0x1F
+    }
+
+    /// Called when the cpu executes STOP, which clears DIV.
+    fn enter_stop(&mut self) {}
+
+    /// Performs a CGB speed switch if one was armed through KEY1. Called on STOP.
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+
+    /// Marks `pc` as the start of the instruction about to be fetched.
+    fn record_execute(&mut self, _pc: u16) {}
+
+    /// The ROM bank mapped at `addr`, for the profiler.
+    fn rom_bank(&self, _addr: u16) -> usize {
+        0
+    }
+
+    /// Identifies what is mapped at `addr` for the block cache: code at the same
+    /// address and bank is assumed to be the same code. None if code at `addr`
+    /// shouldn't be cached.
+    fn code_bank(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    /// Reads a byte of code for the block cache, without side effects.
+    fn code_byte(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+}
+
+impl<B: Bus + ?Sized> Bus for Box<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        (**self).read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        (**self).write(addr, value)
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        (**self).tick(cycles)
+    }
+
+    fn dma_tick(&mut self, cycles: usize) {
+        (**self).dma_tick(cycles)
+    }
+
+    fn hdma_transfer(&mut self) -> Option<usize> {
+        (**self).hdma_transfer()
+    }
+
+    fn interrupt_enable(&mut self) -> u8 {
+        (**self).interrupt_enable()
+    }
+
+    fn pending_interrupts(&mut self) -> u8 {
+        (**self).pending_interrupts()
+    }
+
+    fn enter_stop(&mut self) {
+        (**self).enter_stop()
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        (**self).switch_speed()
+    }
+
+    fn record_execute(&mut self, pc: u16) {
+        (**self).record_execute(pc)
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        (**self).rom_bank(addr)
+    }
+
+    fn code_bank(&self, addr: u16) -> Option<usize> {
+        (**self).code_bank(addr)
+    }
+
+    fn code_byte(&mut self, addr: u16) -> u8 {
+        (**self).code_byte(addr)
+    }
+}
+
+/// 64K of plain RAM with nothing mapped into it.
+pub struct FlatBus {
+    pub ram: Vec<u8>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        FlatBus::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        Self {
+            ram: vec![0; 0x10000],
+        }
+    }
+
+    /// A bus with `program` loaded at `addr`.
+    pub fn with_program(addr: u16, program: &[u8]) -> Self {
+        let mut bus = FlatBus::new();
+        let start = addr as usize;
+        bus.ram[start..start + program.len()].copy_from_slice(program);
+        bus
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn tick(&mut self, _cycles: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, EmulationMode, R16, R8};
+
+    #[test]
+    fn test_flat_bus_program() {
+        // ld a,$42; ld ($C000),a; ld b,a; halt
+        let bus = FlatBus::with_program(0x0100, &[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x47, 0x76]);
+        let mut cpu = Cpu::with_bus(bus, EmulationMode::Dmg);
+        cpu.pc = 0x0100;
+
+        let cycles: usize = (0..4).map(|_| cpu.tick()).sum();
+
+        assert_eq!(cycles, 8 + 16 + 4 + 4);
+        assert_eq!(cpu.mmu.ram[0xC000], 0x42);
+        assert_eq!(cpu.get_r8(&R8::B), 0x42);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        // ei; nop
+        let mut bus = FlatBus::with_program(0x0100, &[0xFB, 0x00]);
+        bus.write(0xFFFF, 0x04);
+        bus.write(0xFF0F, 0x04);
+
+        let mut cpu: Cpu<Box<dyn Bus>> = Cpu::with_bus(Box::new(bus), EmulationMode::Dmg);
+        cpu.pc = 0x0100;
+        cpu.set_r16(R16::SP, 0xFFFE);
+        cpu.set_ime(false);
+
+        for _ in 0..3 {
+            cpu.tick();
+        }
+
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.mmu.read(0xFF0F), 0x00);
+    }
+}
@@ -1,11 +1,16 @@
 use crate::apu::Apu;
 use crate::cartridge::Cartridge;
-use crate::cpu::{CgbMode, EmulationMode};
-use crate::gpu::Gpu;
+use crate::cpu::{CgbMode, CgbSpeed, EmulationMode};
+use crate::gpu::{Gpu, GpuMode};
+use crate::infrared::Infrared;
 use crate::joypad::Joypad;
 use crate::memory::bootrom::Bootrom;
+use crate::memory::bus::Bus;
+use crate::memory::monitor::{Access, AccessMonitor, Blocked};
+use crate::memory::scheduler::{Component, Scheduler};
 use crate::memory::wram::Wram;
-use crate::timer::Timer;
+use crate::serial::Serial;
+use crate::timer::{self, Timer};
 
 const HRAM_SIZE: usize = 0x007F;
 const HRAM_OFFSET: u16 = 0xFF80;
@@ -77,13 +82,15 @@ pub struct Mmu {
     pub hdma: Hdma,
     pub oam_dma: OamDma,
     pub timer: Timer,
+    pub serial: Serial,
+    pub infrared: Infrared,
     wram: Wram,
     hram: [u8; HRAM_SIZE],
-    serial_out: u8,
     emu_mode: EmulationMode,
     pub cgb_mode: CgbMode,
-    request_serial_int: bool,
     oam_dma_cycles: usize,
+    monitor: Option<AccessMonitor>,
+    scheduler: Scheduler,
 }
 
 impl Mmu {
@@ -98,13 +105,15 @@ impl Mmu {
             hdma: Hdma::default(),
             oam_dma: OamDma::default(),
             timer: Timer::new(emu_mode.clone()),
+            serial: Serial::new(emu_mode.clone()),
+            infrared: Infrared::new(emu_mode.clone()),
             wram: Wram::new(),
             hram: [0; HRAM_SIZE],
-            serial_out: 0,
             emu_mode,
             cgb_mode: CgbMode::new(),
-            request_serial_int: false,
             oam_dma_cycles: 0,
+            monitor: None,
+            scheduler: Scheduler::new(),
         }
     }
 
@@ -151,8 +160,8 @@ impl Mmu {
         }
 
         for _ in 0..16 {
-            let value = self.get_byte(self.hdma.src);
-            self.set_byte(0x8000 | (self.hdma.dst & 0x1FFF), value);
+            let value = self.read_byte(self.hdma.src);
+            self.write_byte(0x8000 | (self.hdma.dst & 0x1FFF), value);
             self.hdma.src += 1;
             self.hdma.dst += 1;
         }
@@ -182,10 +191,10 @@ impl Mmu {
             self.oam_dma_cycles -= 4;
 
             if self.oam_dma.src_addr < 0xE000 {
-                self.gpu.oam[self.oam_dma.i as usize] = self.get_byte(self.oam_dma.src_addr);
+                self.gpu.oam[self.oam_dma.i as usize] = self.read_byte(self.oam_dma.src_addr);
             } else {
                 self.gpu.oam[self.oam_dma.i as usize] =
-                    self.get_byte(self.oam_dma.src_addr & !0x2000);
+                    self.read_byte(self.oam_dma.src_addr & !0x2000);
             }
 
             self.oam_dma.i += 1;
@@ -194,22 +203,223 @@ impl Mmu {
     }
 
     pub fn apu_tick(&mut self, cycles: usize) {
-        self.apu.tick(cycles);
+        if self.scheduler.advance(Component::Apu, cycles) {
+            self.catch_up(Component::Apu);
+        }
     }
 
     pub fn gpu_tick(&mut self, cycles: usize) {
-        self.gpu.tick(cycles);
+        if self.scheduler.advance(Component::Gpu, cycles) {
+            self.catch_up(Component::Gpu);
+        }
     }
 
     pub fn timer_tick(&mut self, cycles: usize) {
-        self.timer.tick(cycles);
+        if self.scheduler.advance(Component::Timer, cycles) {
+            self.catch_up(Component::Timer);
+        }
+    }
+
+    /// Runs `component` up to the present. See memory/scheduler.rs.
+    pub fn catch_up(&mut self, component: Component) {
+        let cycles = self.scheduler.take(component);
+
+        let next_event = match component {
+            Component::Timer => {
+                let counter = self.timer.divider.counter;
+                self.timer.tick(cycles);
+
+                let bit = self.sequencer_bit();
+                for _ in 0..timer::falls(counter, cycles, bit) {
+                    self.clock_frame_sequencer();
+                }
+
+                if let Some(serial_bit) = self.serial.clock_bit() {
+                    for _ in 0..timer::falls(counter, cycles, serial_bit) {
+                        self.serial.internal_clock();
+                    }
+                }
+
+                let counter = self.timer.divider.counter;
+                let next_event = self
+                    .timer
+                    .cycles_until_event()
+                    .min(timer::clocks_until_fall(counter, bit));
+
+                match self.serial.clock_bit() {
+                    Some(serial_bit) => {
+                        next_event.min(timer::clocks_until_fall(counter, serial_bit))
+                    }
+                    None => next_event,
+                }
+            }
+            Component::Gpu => {
+                if cycles > 0 {
+                    self.gpu.tick(cycles);
+                }
+                self.gpu.cycles_until_event()
+            }
+            Component::Apu => {
+                self.apu.tick(cycles);
+                self.apu.cycles_until_event()
+            }
+        };
+
+        self.scheduler.schedule(component, next_event);
+    }
+
+    // The system counter bit whose falling edge clocks the APU frame sequencer, 512 Hz
+    // in both speeds.
+    fn sequencer_bit(&self) -> u16 {
+        match self.cgb_mode.speed {
+            CgbSpeed::Normal => 12,
+            CgbSpeed::Double => 13,
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        self.catch_up(Component::Apu);
+        self.apu.sequencer_tick();
+    }
+
+    // DIV writes and STOP clear the system counter, which counts as a falling edge
+    // for every bit that was set.
+    fn reset_system_counter(&mut self) {
+        self.catch_up(Component::Timer);
+
+        let counter = self.timer.divider.counter;
+        self.timer.set_byte(0xFF04, 0);
+
+        if counter & (1 << self.sequencer_bit()) != 0 {
+            self.clock_frame_sequencer();
+        }
+
+        self.catch_up(Component::Timer);
+    }
+
+    /// Runs every component up to the present, for looking at their state directly.
+    pub fn catch_up_all(&mut self) {
+        self.catch_up(Component::Timer);
+        self.catch_up(Component::Gpu);
+        self.catch_up(Component::Apu);
+    }
+
+    /// Ticks every component on every M-cycle instead of catching up lazily.
+    pub fn set_lockstep(&mut self, lockstep: bool) {
+        self.catch_up_all();
+        self.scheduler.lockstep = lockstep;
+    }
+
+    // The component owning `addr`, if it has to be up to date for it to be accessed.
+    fn owner(addr: u16) -> Option<Component> {
+        match addr {
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => Some(Component::Gpu),
+            // The serial clock comes from the system counter.
+            0xFF01..=0xFF02 | 0xFF04..=0xFF07 => Some(Component::Timer),
+            0xFF10..=0xFF3F => Some(Component::Apu),
+            0xFF40..=0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B => Some(Component::Gpu),
+            _ => None,
+        }
     }
 
     pub fn screen(&self) -> *const u8 {
         self.gpu.screen()
     }
 
+    /// Drains the bytes sent over the serial port so far.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+
+    pub fn enable_monitor(&mut self) {
+        if self.monitor.is_none() {
+            self.monitor = Some(AccessMonitor::new());
+        }
+    }
+
+    pub fn disable_monitor(&mut self) -> Option<AccessMonitor> {
+        self.monitor.take()
+    }
+
+    pub fn monitor(&self) -> Option<&AccessMonitor> {
+        self.monitor.as_ref()
+    }
+
     pub fn get_byte(&mut self, addr: u16) -> u8 {
+        if self.monitor.is_some() {
+            self.monitor_access(Access::Read, addr, 0);
+        }
+
+        self.read_byte(addr)
+    }
+
+    pub fn set_byte(&mut self, addr: u16, value: u8) {
+        if self.monitor.is_some() {
+            self.monitor_access(Access::Write, addr, value);
+        }
+
+        self.write_byte(addr, value);
+    }
+
+    fn monitor_access(&mut self, access: Access, addr: u16, value: u8) {
+        let reason = self.blocked_reason(addr, access == Access::Write);
+        let ly = self.gpu.ly();
+
+        if let Some(monitor) = &mut self.monitor {
+            monitor.record(access, addr);
+
+            if let Some(reason) = reason {
+                monitor.record_violation(access, addr, value, ly, reason);
+            }
+        }
+    }
+
+    /// Mirrors the access rules applied in `read_byte`/`write_byte` and `Gpu`.
+    fn blocked_reason(&self, addr: u16, write: bool) -> Option<Blocked> {
+        let display_enabled = !write || self.gpu.display_enabled();
+
+        match addr {
+            0x8000..=0x9FFF => match self.gpu.mode() {
+                GpuMode::PixelTransfer if display_enabled => Some(Blocked::VramInPixelTransfer),
+                _ => None,
+            },
+            0xFE00..=0xFE9F if self.gpu.oam_dma_active || self.oam_dma.restarting => {
+                Some(Blocked::OamInDma)
+            }
+            0xFE00..=0xFE9F => match self.gpu.mode() {
+                GpuMode::OamSearch if display_enabled => Some(Blocked::OamInOamSearch),
+                GpuMode::PixelTransfer if display_enabled => Some(Blocked::OamInPixelTransfer),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        match Mmu::owner(addr) {
+            Some(component) => {
+                self.catch_up(component);
+                let value = self.read_mapped(addr);
+                self.catch_up(component);
+                value
+            }
+            None => self.read_mapped(addr),
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match Mmu::owner(addr) {
+            Some(component) => {
+                self.catch_up(component);
+                self.write_mapped(addr, value);
+                // The write may have moved the next event.
+                self.catch_up(component);
+            }
+            None => self.write_mapped(addr, value),
+        }
+    }
+
+    fn read_mapped(&mut self, addr: u16) -> u8 {
         match addr {
             // 0000-0100   256 byte Boot ROM
             0x0000..=0x00FF => {
@@ -238,12 +448,11 @@ impl Mmu {
             // FF00-FF7F   I/O Ports
             0xFF00..=0xFF3F => match addr {
                 0xFF00 => self.joypad.get_byte(addr),
-                0xFF01 => self.serial_out,
-                0xFF02 => 0x7E,
+                0xFF01..=0xFF02 => self.serial.get_byte(addr),
                 0xFF04..=0xFF07 => self.timer.get_byte(addr),
                 0xFF0F => {
                     0xE0 | (self.joypad.request_joypad_int as u8) << 4
-                        | (self.request_serial_int as u8) << 3
+                        | (self.serial.request_serial_int as u8) << 3
                         | (self.timer.request_timer_int as u8) << 2
                         | (self.gpu.request_lcd_int as u8) << 1
                         | (self.gpu.request_vblank_int as u8)
@@ -266,6 +475,7 @@ impl Mmu {
                     EmulationMode::Cgb => self.gpu.get_byte(addr),
                 },
                 0xFF51..=0xFF54 => 0xFF,
+                0xFF56 => self.infrared.get_byte(),
                 0xFF55 => match self.emu_mode {
                     EmulationMode::Dmg => 0xFF,
                     EmulationMode::Cgb => match self.hdma.hdma_type {
@@ -291,7 +501,7 @@ impl Mmu {
         }
     }
 
-    pub fn set_byte(&mut self, addr: u16, value: u8) {
+    fn write_mapped(&mut self, addr: u16, value: u8) {
         match addr {
             // 0000-3FFF   16KB ROM Bank 0
             0x0000..=0x7FFF => self.cartridge.set_byte(addr, value),
@@ -314,16 +524,14 @@ impl Mmu {
             // FF00-FF7F   I/O Ports
             0xFF00..=0xFF3F => match addr {
                 0xFF00 => self.joypad.set_byte(addr, value),
-                0xFF01 => {
-                    println!("Serial out: {}", value as char);
-                    self.serial_out = value;
-                }
-                0xFF04..=0xFF07 => self.timer.set_byte(addr, value),
+                0xFF01..=0xFF02 => self.serial.set_byte(addr, value),
+                0xFF04 => self.reset_system_counter(),
+                0xFF05..=0xFF07 => self.timer.set_byte(addr, value),
                 0xFF0F => {
                     self.gpu.request_vblank_int = (value & 0x01) != 0;
                     self.gpu.request_lcd_int = (value & 0x02) != 0;
                     self.timer.request_timer_int = (value & 0x04) != 0;
-                    self.request_serial_int = (value & 0x08) != 0;
+                    self.serial.request_serial_int = (value & 0x08) != 0;
                     self.joypad.request_joypad_int = (value & 0x10) != 0;
                 }
                 0xFF10..=0xFF1E => self.apu.set_byte(addr, value),
@@ -376,6 +584,7 @@ impl Mmu {
                     };
                     self.hdma.blocks = value & 0x7F;
                 }
+                0xFF56 => self.infrared.set_byte(value),
                 0xFF68..=0xFF6B if self.emu_mode == EmulationMode::Cgb => {
                     self.gpu.set_byte(addr, value)
                 }
@@ -410,3 +619,175 @@ impl Mmu {
         self.oam_dma.restarting = false;
     }
 }
+
+impl Bus for Mmu {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.get_byte(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.set_byte(addr, value);
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.timer_tick(cycles);
+
+        match self.cgb_mode.speed {
+            CgbSpeed::Normal => {
+                self.gpu_tick(cycles);
+                self.apu_tick(cycles);
+            }
+            CgbSpeed::Double => {
+                self.gpu_tick(cycles >> 1);
+                self.apu_tick(cycles >> 1);
+            }
+        }
+    }
+
+    fn dma_tick(&mut self, cycles: usize) {
+        if self.oam_dma.active {
+            self.oam_dma_tick(cycles);
+        }
+    }
+
+    fn hdma_transfer(&mut self) -> Option<usize> {
+        match self.hdma.hdma_type {
+            HdmaType::GPDma => self.gdma_tick(),
+            HdmaType::HBlankDma if self.gpu.hdma_flag => self.hdma_tick(),
+            _ => return None,
+        }
+
+        Some(match self.cgb_mode.speed {
+            CgbSpeed::Normal => 32,
+            CgbSpeed::Double => 64,
+        })
+    }
+
+    fn interrupt_enable(&mut self) -> u8 {
+        self.ie
+    }
+
+    fn pending_interrupts(&mut self) -> u8 {
+        self.ie & self.get_byte(0xFF0F) & 0x1F
+    }
+
+    fn enter_stop(&mut self) {
+        self.reset_system_counter();
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        if self.cgb_mode.prepare_speed_switch == 0x0 {
+            return false;
+        }
+
+        // The gpu and apu run at half the rate after this, and the frame sequencer
+        // watches a different bit.
+        self.catch_up_all();
+
+        self.cgb_mode.speed = match self.cgb_mode.speed {
+            CgbSpeed::Normal => CgbSpeed::Double,
+            CgbSpeed::Double => CgbSpeed::Normal,
+        };
+        self.cgb_mode.prepare_speed_switch = 0x0;
+        self.catch_up(Component::Timer);
+
+        true
+    }
+
+    /// Marks `pc` as the start of the instruction being executed.
+    #[inline]
+    fn record_execute(&mut self, pc: u16) {
+        if let Some(monitor) = &mut self.monitor {
+            monitor.record(Access::Execute, pc);
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.cartridge.rom_bank(addr)
+    }
+
+    fn code_bank(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x0000..=0x00FF if self.bootrom.is_active => None,
+            0x0000..=0x7FFF => Some(self.cartridge.rom_bank(addr)),
+            0xC000..=0xCFFF => Some(0),
+            0xD000..=0xDFFF => Some(self.wram.bank()),
+            0xFF80..=0xFFFE => Some(0),
+            _ => None,
+        }
+    }
+
+    fn code_byte(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clocks until a channel 1 note with a length of 2 runs out.
+    fn length_expiry_clocks(speed: CgbSpeed) -> usize {
+        let mut mmu = Mmu::new(vec![0; 0x8000], EmulationMode::Cgb);
+        mmu.cgb_mode.speed = speed;
+
+        mmu.set_byte(0xFF04, 0);
+        mmu.set_byte(0xFF26, 0x80);
+        mmu.set_byte(0xFF12, 0xF0);
+        mmu.set_byte(0xFF11, 0x3E);
+        mmu.set_byte(0xFF14, 0xC0);
+
+        let mut clocks = 0;
+        while mmu.get_byte(0xFF26) & 0x01 != 0 {
+            mmu.tick(4);
+            clocks += 4;
+        }
+        clocks
+    }
+
+    #[test]
+    fn test_frame_sequencer_follows_div() {
+        let normal = length_expiry_clocks(CgbSpeed::Normal);
+        assert_eq!(normal, 3 * 8192);
+        // DIV runs twice as fast in double speed, so the sequencer uses the next bit.
+        assert_eq!(length_expiry_clocks(CgbSpeed::Double), 2 * normal);
+    }
+
+    #[test]
+    fn test_div_write_clocks_frame_sequencer() {
+        let mut mmu = Mmu::new(vec![0; 0x8000], EmulationMode::Dmg);
+        mmu.set_byte(0xFF04, 0);
+        mmu.set_byte(0xFF26, 0x80);
+        mmu.set_byte(0xFF12, 0xF0);
+        mmu.set_byte(0xFF11, 0x3F);
+        mmu.set_byte(0xFF14, 0xC0);
+
+        // Bit 12 is set halfway through the sequencer period, resetting DIV then
+        // steps the sequencer early.
+        for _ in 0..4096 / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.get_byte(0xFF26) & 0x01, 0x01);
+        mmu.set_byte(0xFF04, 0);
+        assert_eq!(mmu.get_byte(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_serial_transfer_timing() {
+        let mut mmu = Mmu::new(vec![0; 0x8000], EmulationMode::Dmg);
+        mmu.set_byte(0xFF04, 0);
+        mmu.set_byte(0xFF01, 0x55);
+        mmu.set_byte(0xFF02, 0x81);
+
+        // 8 bits at 8192 Hz.
+        for _ in 0..(8 * 512 - 4) / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.get_byte(0xFF0F) & 0x08, 0);
+
+        mmu.tick(4);
+        assert_eq!(mmu.get_byte(0xFF0F) & 0x08, 0x08);
+        assert_eq!(mmu.get_byte(0xFF01), 0xFF);
+        assert_eq!(mmu.take_serial_output(), vec![0x55]);
+    }
+}
@@ -1,3 +1,6 @@
 pub mod bootrom;
+pub mod bus;
 pub mod mmu;
+pub mod monitor;
+pub mod scheduler;
 pub mod wram;
@@ -0,0 +1,198 @@
+// Opt-in bus instrumentation for homebrew debugging. Counts every CPU read, write
+// and opcode fetch per address, and logs accesses that real hardware would block:
+// VRAM during mode 3, OAM during modes 2 and 3, and OAM while an OAM DMA is running.
+
+use std::fmt;
+
+const ADDRESS_SPACE: usize = 0x10000;
+const MAX_VIOLATIONS: usize = 4096;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Blocked {
+    VramInPixelTransfer,
+    OamInOamSearch,
+    OamInPixelTransfer,
+    OamInDma,
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub access: Access,
+    pub addr: u16,
+    pub value: u8,
+    pub pc: u16,
+    pub ly: u8,
+    pub reason: Blocked,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.reason {
+            Blocked::VramInPixelTransfer => "VRAM during mode 3",
+            Blocked::OamInOamSearch => "OAM during mode 2",
+            Blocked::OamInPixelTransfer => "OAM during mode 3",
+            Blocked::OamInDma => "OAM during OAM DMA",
+        };
+
+        match self.access {
+            Access::Write => write!(
+                f,
+                "PC={:04X} LY={:3} write {:04X} <- {:02X}: {}",
+                self.pc, self.ly, self.addr, self.value, reason
+            ),
+            _ => write!(
+                f,
+                "PC={:04X} LY={:3} read  {:04X}: {}",
+                self.pc, self.ly, self.addr, reason
+            ),
+        }
+    }
+}
+
+pub struct AccessMonitor {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    executes: Vec<u32>,
+    violations: Vec<Violation>,
+    dropped_violations: usize,
+    pc: u16,
+}
+
+impl AccessMonitor {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0; ADDRESS_SPACE],
+            writes: vec![0; ADDRESS_SPACE],
+            executes: vec![0; ADDRESS_SPACE],
+            violations: Vec::new(),
+            dropped_violations: 0,
+            pc: 0,
+        }
+    }
+
+    pub fn record(&mut self, access: Access, addr: u16) {
+        let counts = match access {
+            Access::Read => &mut self.reads,
+            Access::Write => &mut self.writes,
+            Access::Execute => {
+                self.pc = addr;
+                &mut self.executes
+            }
+        };
+
+        counts[addr as usize] = counts[addr as usize].saturating_add(1);
+    }
+
+    pub fn record_violation(
+        &mut self,
+        access: Access,
+        addr: u16,
+        value: u8,
+        ly: u8,
+        reason: Blocked,
+    ) {
+        if self.violations.len() == MAX_VIOLATIONS {
+            self.dropped_violations += 1;
+            return;
+        }
+
+        self.violations.push(Violation {
+            access,
+            addr,
+            value,
+            pc: self.pc,
+            ly,
+            reason,
+        });
+    }
+
+    pub fn counts(&self, access: Access) -> &[u32] {
+        match access {
+            Access::Read => &self.reads,
+            Access::Write => &self.writes,
+            Access::Execute => &self.executes,
+        }
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn dropped_violations(&self) -> usize {
+        self.dropped_violations
+    }
+
+    pub fn clear(&mut self) {
+        *self = AccessMonitor::new();
+    }
+
+    /// One intensity byte per address (64K), log scaled so that rarely touched
+    /// addresses still show up next to tight loops.
+    pub fn heatmap(&self, access: Access) -> Vec<u8> {
+        let counts = self.counts(access);
+        let max = counts.iter().cloned().max().unwrap_or(0);
+
+        if max == 0 {
+            return vec![0; ADDRESS_SPACE];
+        }
+
+        let scale = 254.0 / ((max as f32) + 1.0).ln();
+
+        counts
+            .iter()
+            .map(|&count| match count {
+                0 => 0,
+                n => 1 + (((n as f32) + 1.0).ln() * scale).round() as u8,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_scaling() {
+        let mut monitor = AccessMonitor::new();
+
+        for _ in 0..1000 {
+            monitor.record(Access::Read, 0xC000);
+        }
+        monitor.record(Access::Read, 0xC001);
+
+        let heatmap = monitor.heatmap(Access::Read);
+        assert_eq!(heatmap.len(), 0x10000);
+        assert_eq!(heatmap[0xC000], 255);
+        assert!(heatmap[0xC001] > 0 && heatmap[0xC001] < heatmap[0xC000]);
+        assert_eq!(heatmap[0xC002], 0);
+    }
+
+    #[test]
+    fn test_violation_carries_pc() {
+        let mut monitor = AccessMonitor::new();
+
+        monitor.record(Access::Execute, 0x0150);
+        monitor.record_violation(
+            Access::Write,
+            0x8000,
+            0x3C,
+            42,
+            Blocked::VramInPixelTransfer,
+        );
+
+        let violation = &monitor.violations()[0];
+        assert_eq!(violation.pc, 0x0150);
+        assert_eq!(
+            violation.to_string(),
+            "PC=0150 LY= 42 write 8000 <- 3C: VRAM during mode 3"
+        );
+    }
+}
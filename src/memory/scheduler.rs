@@ -0,0 +1,98 @@
+// Lazy timing for the timer, gpu and apu. Instead of ticking every component on
+// every M-cycle, the Mmu owes each one the clocks that passed since it last ran and
+// only hands them over (a catch-up) when:
+//  - the cpu touches one of the component's registers or its memory, or
+//  - the component's next event is due: a TIMA increment, frame sequencer step or
+//    serial clock (falling edges of the system counter, all handled by the timer's
+//    catch-up), the end of HBlank or of a VBlank line (LY, STAT and interrupt
+//    changes), or a full audio buffer.
+// Between events nothing a component does can be seen from outside, so the result
+// is the same as ticking in lock-step. Each component reports how far away its next
+// event is after every catch-up (`cycles_until_event`), 0 meaning it has to run on
+// every tick, which is how the gpu handles modes 2 and 3. Nothing is scheduled
+// more than a frame ahead, so the clocks owed can't overflow while a component
+// has nothing to do (like the gpu with the LCD off).
+//
+// Lock-step mode turns all of this off, for checking that the two agree.
+
+// One frame in normal speed.
+const MAX_DUE: usize = 70224;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Component {
+    Timer = 0,
+    Gpu = 1,
+    Apu = 2,
+}
+
+pub struct Scheduler {
+    // Clocks owed to each component.
+    pending: [usize; 3],
+    // Clocks after the last catch-up at which each component's next event is due.
+    due: [usize; 3],
+    pub lockstep: bool,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            pending: [0; 3],
+            due: [0; 3],
+            lockstep: false,
+        }
+    }
+
+    /// Owes `cycles` more clocks to `component`, returns true if it has to catch
+    /// up now.
+    #[inline]
+    pub fn advance(&mut self, component: Component, cycles: usize) -> bool {
+        let i = component as usize;
+        self.pending[i] += cycles;
+        self.lockstep || self.pending[i] >= self.due[i]
+    }
+
+    /// Takes the clocks owed to `component`.
+    #[inline]
+    pub fn take(&mut self, component: Component) -> usize {
+        std::mem::take(&mut self.pending[component as usize])
+    }
+
+    /// Sets when `component` next has to run, in clocks from now.
+    #[inline]
+    pub fn schedule(&mut self, component: Component, cycles: usize) {
+        self.due[component as usize] = cycles.min(MAX_DUE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_up_when_due() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Component::Timer, 10);
+
+        assert!(!scheduler.advance(Component::Timer, 4));
+        assert!(!scheduler.advance(Component::Timer, 4));
+        assert!(scheduler.advance(Component::Timer, 4));
+        assert_eq!(scheduler.take(Component::Timer), 12);
+
+        // Components are scheduled independently.
+        assert!(scheduler.advance(Component::Apu, 4));
+
+        scheduler.lockstep = true;
+        scheduler.schedule(Component::Gpu, usize::MAX);
+        assert!(scheduler.advance(Component::Gpu, 4));
+    }
+
+    #[test]
+    fn test_idle_component_still_catches_up() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Component::Gpu, usize::MAX);
+
+        assert!(!scheduler.advance(Component::Gpu, MAX_DUE - 4));
+        assert!(scheduler.advance(Component::Gpu, 4));
+        assert_eq!(scheduler.take(Component::Gpu), MAX_DUE);
+    }
+}
@@ -15,6 +15,10 @@ impl Wram {
         }
     }
 
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
     pub fn get_byte(&mut self, addr: u16) -> u8 {
         match addr {
             0xC000..=0xCFFF => self.wram[addr as usize - WRAM_OFFSET],
@@ -0,0 +1,420 @@
+// An opt-in instruction level profiler. The cpu reports every executed instruction
+// together with the ROM bank it was fetched from, which lets us build a hot-spot
+// report per (bank, pc) as well as flame graph friendly folded stacks.
+//
+// Folded stacks use the format understood by Brendan Gregg's flamegraph.pl and
+// inferno: one line per unique call stack, frames separated by `;`, followed by
+// the number of cycles spent in that stack.
+//
+// A CALL's or RET's own cycles count towards the frame it was executed in: the
+// shadow stack only changes once the instruction has been recorded.
+//
+// Cycles where no instruction runs are counted too, at the top of whatever stack
+// was current: halted, stopped (waiting for a joypad press or switching speed)
+// and stalled while a CGB general purpose or HBlank DMA copies its blocks.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const MAX_STACK_DEPTH: usize = 256;
+const INTERRUPT_NAMES: [&str; 5] = ["vblank", "lcd_stat", "timer", "serial", "joypad"];
+const IDLE_NAMES: [&str; 3] = ["halt", "stop", "dma"];
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct Location {
+    pub bank: usize,
+    pub pc: u16,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Hits {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+// Why no instruction ran, indexes IDLE_NAMES.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Idle {
+    Halt,
+    Stop,
+    Dma,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Frame {
+    Call(Location),
+    Interrupt(usize),
+}
+
+pub struct Profiler {
+    hits: HashMap<Location, Hits>,
+    idle_cycles: [u64; 3],
+    interrupt_cycles: [u64; 5],
+    interrupt_counts: [u64; 5],
+    total_cycles: u64,
+    frames: Vec<Frame>,
+    // Address of the return address pushed for each frame, used to resync the
+    // shadow stack when code pops return addresses by hand.
+    return_slots: Vec<u16>,
+    // Calls (`Some`) and returns (`None`) made by the instruction being executed,
+    // with the stack pointer they came with.
+    pending: Vec<(Option<Frame>, u16)>,
+    folded: HashMap<Vec<Frame>, u64>,
+    folded_idle: [HashMap<Vec<Frame>, u64>; 3],
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            hits: HashMap::new(),
+            idle_cycles: [0; 3],
+            interrupt_cycles: [0; 5],
+            interrupt_counts: [0; 5],
+            total_cycles: 0,
+            frames: Vec::with_capacity(MAX_STACK_DEPTH),
+            return_slots: Vec::with_capacity(MAX_STACK_DEPTH),
+            pending: Vec::new(),
+            folded: HashMap::new(),
+            folded_idle: [HashMap::new(), HashMap::new(), HashMap::new()],
+        }
+    }
+
+    /// Records one executed instruction fetched from `bank:pc`.
+    pub fn record_instruction(&mut self, bank: usize, pc: u16, cycles: usize) {
+        let hits = self.hits.entry(Location { bank, pc }).or_default();
+        hits.count += 1;
+        hits.cycles += cycles as u64;
+
+        self.record_cycles(cycles, None);
+        self.apply_pending();
+    }
+
+    /// Records cycles spent halted.
+    pub fn record_halt(&mut self, cycles: usize) {
+        self.record_cycles(cycles, Some(Idle::Halt));
+    }
+
+    /// Records cycles spent in STOP mode.
+    pub fn record_stop(&mut self, cycles: usize) {
+        self.record_cycles(cycles, Some(Idle::Stop));
+    }
+
+    /// Records cycles the cpu was stalled by a GDMA or HDMA block copy.
+    pub fn record_dma(&mut self, cycles: usize) {
+        self.record_cycles(cycles, Some(Idle::Dma));
+    }
+
+    /// Records an interrupt dispatch. The handler frame stays on the shadow stack
+    /// until the matching RET/RETI.
+    pub fn enter_interrupt(&mut self, i: usize, sp: u16, cycles: usize) {
+        self.apply_pending();
+        self.interrupt_counts[i] += 1;
+        self.push_frame(Frame::Interrupt(i), sp);
+        self.record_cycles(cycles, None);
+    }
+
+    /// A call to `bank:pc`, taking effect after the calling instruction is recorded.
+    pub fn enter_call(&mut self, bank: usize, pc: u16, sp: u16) {
+        self.pending
+            .push((Some(Frame::Call(Location { bank, pc })), sp));
+    }
+
+    /// Called before a return pops its address off `sp`. Takes effect after the
+    /// returning instruction is recorded.
+    pub fn leave_call(&mut self, sp: u16) {
+        self.pending.push((None, sp));
+    }
+
+    pub fn reset(&mut self) {
+        *self = Profiler::new();
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn halt_cycles(&self) -> u64 {
+        self.idle_cycles[Idle::Halt as usize]
+    }
+
+    pub fn stop_cycles(&self) -> u64 {
+        self.idle_cycles[Idle::Stop as usize]
+    }
+
+    pub fn dma_cycles(&self) -> u64 {
+        self.idle_cycles[Idle::Dma as usize]
+    }
+
+    pub fn interrupt_cycles(&self) -> &[u64; 5] {
+        &self.interrupt_cycles
+    }
+
+    /// All hit locations, hottest first.
+    pub fn hot_spots(&self) -> Vec<(Location, Hits)> {
+        let mut spots: Vec<(Location, Hits)> =
+            self.hits.iter().map(|(loc, hits)| (*loc, *hits)).collect();
+
+        spots.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    /// Human readable report of the hottest `limit` locations.
+    pub fn report(&self, limit: usize) -> String {
+        let total = self.total_cycles.max(1) as f64;
+        let mut out = String::new();
+
+        writeln!(out, "total cycles: {}", self.total_cycles).unwrap();
+        for (name, cycles) in IDLE_NAMES.iter().zip(self.idle_cycles.iter()) {
+            writeln!(
+                out,
+                "{:<13} {} ({:.2}%)",
+                format!("{}:", name),
+                cycles,
+                *cycles as f64 * 100.0 / total
+            )
+            .unwrap();
+        }
+
+        for (i, name) in INTERRUPT_NAMES.iter().enumerate() {
+            writeln!(
+                out,
+                "int {:<9} {} calls, {} cycles ({:.2}%)",
+                name,
+                self.interrupt_counts[i],
+                self.interrupt_cycles[i],
+                self.interrupt_cycles[i] as f64 * 100.0 / total
+            )
+            .unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "{:>7}  {:>12}  {:>10}  {:>7}",
+            "bank:pc", "cycles", "count", "%"
+        )
+        .unwrap();
+
+        for (loc, hits) in self.hot_spots().iter().take(limit) {
+            writeln!(
+                out,
+                "{:02X}:{:04X}  {:>12}  {:>10}  {:>6.2}%",
+                loc.bank,
+                loc.pc,
+                hits.cycles,
+                hits.count,
+                hits.cycles as f64 * 100.0 / total
+            )
+            .unwrap();
+        }
+
+        out
+    }
+
+    /// Folded stacks suitable for `flamegraph.pl` or `inferno-flamegraph`.
+    pub fn folded_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .folded
+            .iter()
+            .map(|(frames, cycles)| format!("{} {}", fold(frames), cycles))
+            .chain(
+                self.folded_idle
+                    .iter()
+                    .zip(IDLE_NAMES.iter())
+                    .flat_map(|(folded, name)| {
+                        folded.iter().map(move |(frames, cycles)| {
+                            format!("{};{} {}", fold(frames), name, cycles)
+                        })
+                    }),
+            )
+            .collect();
+
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    fn push_frame(&mut self, frame: Frame, sp: u16) {
+        if self.frames.len() == MAX_STACK_DEPTH {
+            self.frames.remove(0);
+            self.return_slots.remove(0);
+        }
+
+        self.frames.push(frame);
+        self.return_slots.push(sp);
+    }
+
+    fn apply_pending(&mut self) {
+        for (frame, sp) in std::mem::take(&mut self.pending) {
+            match frame {
+                Some(frame) => self.push_frame(frame, sp),
+                None => self.pop_frames(sp),
+            }
+        }
+    }
+
+    fn pop_frames(&mut self, sp: u16) {
+        while let Some(&slot) = self.return_slots.last() {
+            if slot > sp {
+                break;
+            }
+
+            self.return_slots.pop();
+            self.frames.pop();
+        }
+    }
+
+    fn record_cycles(&mut self, cycles: usize, idle: Option<Idle>) {
+        if idle.is_some() {
+            self.apply_pending();
+        }
+
+        let cycles = cycles as u64;
+        self.total_cycles += cycles;
+
+        let innermost_interrupt = self.frames.iter().rev().find_map(|frame| match frame {
+            Frame::Interrupt(i) => Some(*i),
+            _ => None,
+        });
+
+        if let Some(i) = innermost_interrupt {
+            self.interrupt_cycles[i] += cycles;
+        }
+
+        let folded = match idle {
+            Some(idle) => {
+                self.idle_cycles[idle as usize] += cycles;
+                &mut self.folded_idle[idle as usize]
+            }
+            None => &mut self.folded,
+        };
+
+        match folded.get_mut(&self.frames[..]) {
+            Some(total) => *total += cycles,
+            None => {
+                folded.insert(self.frames.clone(), cycles);
+            }
+        }
+    }
+}
+
+fn fold(frames: &[Frame]) -> String {
+    let mut out = String::from("main");
+
+    for frame in frames {
+        match frame {
+            Frame::Call(loc) => write!(out, ";{:02X}:{:04X}", loc.bank, loc.pc).unwrap(),
+            Frame::Interrupt(i) => write!(out, ";int_{}", INTERRUPT_NAMES[*i]).unwrap(),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_spots_sorted_by_cycles() {
+        let mut profiler = Profiler::new();
+
+        profiler.record_instruction(0, 0x0150, 4);
+        profiler.record_instruction(1, 0x4000, 16);
+        profiler.record_instruction(0, 0x0150, 4);
+
+        let spots = profiler.hot_spots();
+        assert_eq!(
+            spots[0].0,
+            Location {
+                bank: 1,
+                pc: 0x4000
+            }
+        );
+        assert_eq!(spots[1].1.count, 2);
+        assert_eq!(profiler.total_cycles(), 24);
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let mut profiler = Profiler::new();
+
+        profiler.enter_call(1, 0x4000, 0xFFFC);
+        profiler.record_instruction(0, 0x0150, 24);
+        profiler.record_instruction(1, 0x4000, 8);
+        profiler.enter_interrupt(0, 0xFFFA, 20);
+        profiler.record_instruction(0, 0x0040, 16);
+        profiler.leave_call(0xFFFA);
+        profiler.leave_call(0xFFFC);
+        profiler.record_halt(4);
+
+        let folded = profiler.folded_stacks();
+        assert!(folded.contains("main 24\n"));
+        assert!(folded.contains("main;01:4000 8\n"));
+        assert!(folded.contains("main;01:4000;int_vblank 36\n"));
+        assert!(folded.contains("main;halt 4\n"));
+        assert_eq!(profiler.interrupt_cycles()[0], 36);
+        assert_eq!(profiler.halt_cycles(), 4);
+    }
+
+    #[test]
+    fn test_idle_cycles_counted() {
+        let mut profiler = Profiler::new();
+
+        profiler.record_instruction(0, 0x0150, 4);
+        profiler.record_stop(8);
+        profiler.enter_interrupt(2, 0xFFFC, 20);
+        profiler.record_dma(32);
+
+        assert_eq!(profiler.total_cycles(), 64);
+        assert_eq!(profiler.stop_cycles(), 8);
+        assert_eq!(profiler.dma_cycles(), 32);
+        assert_eq!(profiler.interrupt_cycles()[2], 52);
+
+        let folded = profiler.folded_stacks();
+        assert!(folded.contains("main;stop 8\n"));
+        assert!(folded.contains("main;int_timer;dma 32\n"));
+        assert!(profiler.report(1).contains("dma:          32 (50.00%)"));
+    }
+
+    #[test]
+    fn test_unbalanced_return_resyncs_stack() {
+        let mut profiler = Profiler::new();
+
+        profiler.enter_call(0, 0x0200, 0xFFFC);
+        profiler.record_instruction(0, 0x0150, 24);
+        profiler.enter_call(0, 0x0300, 0xFFFA);
+        profiler.record_instruction(0, 0x0200, 24);
+        // The inner routine discards its return address and returns straight
+        // to the outer caller.
+        profiler.leave_call(0xFFFC);
+        profiler.record_instruction(0, 0x0310, 16);
+        profiler.record_instruction(0, 0x0153, 4);
+
+        let folded = profiler.folded_stacks();
+        assert!(folded.contains("main 28\n"), "{}", folded);
+        assert!(folded.contains("main;00:0200;00:0300 16\n"), "{}", folded);
+    }
+
+    #[test]
+    fn test_call_and_ret_cycles() {
+        let mut profiler = Profiler::new();
+
+        // CALL $4000, NOP, RET, NOP.
+        profiler.enter_call(1, 0x4000, 0xFFFC);
+        profiler.record_instruction(0, 0x0150, 24);
+        profiler.record_instruction(1, 0x4000, 4);
+        profiler.leave_call(0xFFFC);
+        profiler.record_instruction(1, 0x4001, 16);
+        profiler.record_instruction(0, 0x0153, 4);
+
+        let folded = profiler.folded_stacks();
+        assert!(folded.contains("main 28\n"), "{}", folded);
+        assert!(folded.contains("main;01:4000 20\n"), "{}", folded);
+    }
+}
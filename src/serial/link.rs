@@ -0,0 +1,111 @@
+// A link cable between two Game Boys in the same process. Each end is a
+// `SerialDevice` plugged into one of them; the `LinkCable` itself is what the runner
+// uses to move bits across.
+//
+// Whichever side uses the internal clock drives the transfer. Its clock pulses are
+// queued for the other side, which shifts them in through `Serial::external_clock`
+// on the next `sync`. The bit it gets back is read from the other side's shift
+// register as of its last sync, moved along by the pulses still queued, which is
+// what the other side will have shifted out by then. The two sides may be a few
+// clocks apart (see `gameboy::link`), but the bytes exchanged are exact.
+
+use crate::serial::{Serial, SerialDevice};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct End {
+    // SB as of the last sync.
+    sb: u8,
+    // Clock pulses from the other side not delivered yet, with the bit sent on each.
+    pulses: VecDeque<bool>,
+}
+
+#[derive(Default)]
+struct Wire {
+    ends: [End; 2],
+}
+
+pub struct LinkCable {
+    wire: Rc<RefCell<Wire>>,
+}
+
+/// One end of a `LinkCable`.
+pub struct LinkPort {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl LinkCable {
+    /// A cable and its two ends, to plug into side 0 and side 1.
+    pub fn new() -> (LinkCable, LinkPort, LinkPort) {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+
+        (
+            LinkCable { wire: wire.clone() },
+            LinkPort {
+                wire: wire.clone(),
+                side: 0,
+            },
+            LinkPort { wire, side: 1 },
+        )
+    }
+
+    /// Delivers the clock pulses queued for `side` to its serial port and publishes
+    /// its shift register to the other side.
+    pub fn sync(&self, side: usize, serial: &mut Serial) {
+        let mut wire = self.wire.borrow_mut();
+        let end = &mut wire.ends[side];
+
+        while let Some(bit) = end.pulses.pop_front() {
+            serial.external_clock(bit);
+        }
+
+        end.sb = serial.get_byte(0xFF01);
+    }
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        let mut wire = self.wire.borrow_mut();
+        let other = &mut wire.ends[1 - self.side];
+
+        let sb = (other.sb as u16) << other.pulses.len();
+        other.pulses.push_back(bit);
+
+        sb & 0x80 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::EmulationMode;
+
+    #[test]
+    fn test_exchange_byte() {
+        let (cable, port0, _port1) = LinkCable::new();
+
+        let mut master = Serial::new(EmulationMode::Dmg);
+        let mut slave = Serial::new(EmulationMode::Dmg);
+        master.set_byte(0xFF01, 0x12);
+        slave.set_byte(0xFF01, 0xC3);
+        slave.set_byte(0xFF02, 0x80);
+        master.set_byte(0xFF02, 0x81);
+
+        master.connect(Box::new(port0));
+        cable.sync(1, &mut slave);
+
+        // The slave lags behind the whole byte.
+        for _ in 0..8 {
+            master.internal_clock();
+        }
+        cable.sync(1, &mut slave);
+
+        assert_eq!(master.get_byte(0xFF01), 0xC3);
+        assert_eq!(slave.get_byte(0xFF01), 0x12);
+        assert!(master.request_serial_int);
+        assert!(slave.request_serial_int);
+    }
+}
@@ -0,0 +1,234 @@
+// Serial port. SB (FF01) is a shift register: every serial clock shifts its top bit
+// out to the other end of the cable and shifts the bit coming back in at the bottom.
+// SC (FF02) bit 7 starts a transfer, bit 0 selects the internal clock and, on CGB,
+// bit 1 the fast internal clock. After 8 bits bit 7 is cleared and the serial
+// interrupt is requested.
+//
+// The internal clock is a falling edge of the system counter: bit 8 (8192 Hz) or
+// bit 3 (262144 Hz) in fast mode, both doubled in double speed. With the external
+// clock nothing happens until whatever is on the other end clocks us through
+// `external_clock`.
+//
+// References: https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+
+pub mod link;
+pub mod net;
+pub mod printer;
+
+use crate::cpu::EmulationMode;
+
+const NORMAL_CLOCK_BIT: u16 = 8;
+const FAST_CLOCK_BIT: u16 = 3;
+
+/// Whatever is plugged into the link port.
+pub trait SerialDevice {
+    /// Called once per bit clocked by this Game Boy, with the bit it sends. Returns
+    /// the bit sent back.
+    fn exchange_bit(&mut self, bit: bool) -> bool;
+}
+
+/// Nothing plugged in. The data line is pulled up so every byte received is $FF.
+pub struct NoCable;
+
+impl SerialDevice for NoCable {
+    fn exchange_bit(&mut self, _bit: bool) -> bool {
+        true
+    }
+}
+
+pub struct Serial {
+    pub request_serial_int: bool,
+    sb: u8,
+    sc: u8,
+    bits: u8,
+    device: Box<dyn SerialDevice>,
+    // Every byte sent, for test ROMs that report over serial.
+    log: Vec<u8>,
+    emu_mode: EmulationMode,
+}
+
+impl Serial {
+    pub fn new(emu_mode: EmulationMode) -> Self {
+        Self {
+            request_serial_int: false,
+            sb: 0,
+            sc: 0,
+            bits: 0,
+            device: Box::new(NoCable),
+            log: Vec::new(),
+            emu_mode,
+        }
+    }
+
+    /// Plugs `device` into the link port, returning what was there before.
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn get_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => match self.emu_mode {
+                EmulationMode::Dmg => 0x7E | self.sc,
+                EmulationMode::Cgb => 0x7C | self.sc,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                self.sc = match self.emu_mode {
+                    EmulationMode::Dmg => value & 0x81,
+                    EmulationMode::Cgb => value & 0x83,
+                };
+
+                if self.transferring() {
+                    self.bits = 0;
+
+                    // Logged when sent rather than when done, test ROMs don't always
+                    // wait for one byte to finish before sending the next.
+                    if self.clock_bit().is_some() {
+                        self.log.push(self.sb);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The system counter bit clocking a transfer in progress, if it uses the
+    /// internal clock.
+    pub fn clock_bit(&self) -> Option<u16> {
+        if !self.transferring() || self.sc & 0x01 == 0 {
+            None
+        } else if self.sc & 0x02 != 0 {
+            Some(FAST_CLOCK_BIT)
+        } else {
+            Some(NORMAL_CLOCK_BIT)
+        }
+    }
+
+    /// Whether a transfer was started with the external clock and is waiting for
+    /// the other side to clock it.
+    pub fn waiting_for_clock(&self) -> bool {
+        self.transferring() && self.sc & 0x01 == 0
+    }
+
+    /// One tick of the internal clock.
+    pub fn internal_clock(&mut self) {
+        if self.clock_bit().is_none() {
+            return;
+        }
+
+        let bit_in = self.device.exchange_bit(self.sb & 0x80 != 0);
+        self.shift(bit_in);
+    }
+
+    /// One tick of a clock driven by the other end of the cable. Takes the bit it
+    /// sends and returns ours.
+    pub fn external_clock(&mut self, bit_in: bool) -> bool {
+        let bit_out = self.sb & 0x80 != 0;
+
+        if self.waiting_for_clock() {
+            if self.bits == 0 {
+                self.log.push(self.sb);
+            }
+            self.shift(bit_in);
+        }
+
+        bit_out
+    }
+
+    /// Drains the bytes sent so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.log)
+    }
+
+    fn transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+
+    fn shift(&mut self, bit_in: bool) {
+        self.sb = self.sb << 1 | bit_in as u8;
+        self.bits += 1;
+
+        if self.bits == 8 {
+            self.bits = 0;
+            self.sc &= 0x7F;
+            self.request_serial_int = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Loopback;
+
+    impl SerialDevice for Loopback {
+        fn exchange_bit(&mut self, bit: bool) -> bool {
+            bit
+        }
+    }
+
+    #[test]
+    fn test_no_cable_receives_ff() {
+        let mut serial = Serial::new(EmulationMode::Dmg);
+        serial.set_byte(0xFF01, 0x42);
+        serial.set_byte(0xFF02, 0x81);
+        assert_eq!(serial.clock_bit(), Some(NORMAL_CLOCK_BIT));
+
+        for _ in 0..7 {
+            serial.internal_clock();
+        }
+        assert!(!serial.request_serial_int);
+        assert_eq!(serial.get_byte(0xFF02), 0xFF);
+
+        serial.internal_clock();
+        assert!(serial.request_serial_int);
+        assert_eq!(serial.get_byte(0xFF01), 0xFF);
+        assert_eq!(serial.get_byte(0xFF02), 0x7F);
+        assert_eq!(serial.clock_bit(), None);
+        assert_eq!(serial.take_output(), vec![0x42]);
+    }
+
+    #[test]
+    fn test_external_clock() {
+        let mut serial = Serial::new(EmulationMode::Cgb);
+        serial.connect(Box::new(Loopback));
+        serial.set_byte(0xFF01, 0xA5);
+
+        // Not started, the internal clock does nothing.
+        serial.internal_clock();
+        assert_eq!(serial.get_byte(0xFF01), 0xA5);
+
+        serial.set_byte(0xFF02, 0x80);
+        assert_eq!(serial.clock_bit(), None);
+
+        let mut received = 0;
+        for i in 0..8 {
+            let bit = 0x3C >> (7 - i) & 1 != 0;
+            received = received << 1 | serial.external_clock(bit) as u8;
+        }
+
+        assert_eq!(received, 0xA5);
+        assert_eq!(serial.get_byte(0xFF01), 0x3C);
+        assert!(serial.request_serial_int);
+    }
+
+    #[test]
+    fn test_fast_clock_is_cgb_only() {
+        let mut dmg = Serial::new(EmulationMode::Dmg);
+        dmg.set_byte(0xFF02, 0x83);
+        assert_eq!(dmg.clock_bit(), Some(NORMAL_CLOCK_BIT));
+
+        let mut cgb = Serial::new(EmulationMode::Cgb);
+        cgb.set_byte(0xFF02, 0x83);
+        assert_eq!(cgb.clock_bit(), Some(FAST_CLOCK_BIT));
+        assert_eq!(cgb.get_byte(0xFF02), 0xFF);
+    }
+}
@@ -0,0 +1,447 @@
+// Link cable between two processes (or browser tabs), over anything that can carry
+// small messages: a TCP socket for the native runner, a channel in tests, a
+// WebSocket in a browser front end (implement `LinkTransport` for it).
+//
+// Bits can't go back and forth over a network as fast as the serial clock, so the
+// link works a byte at a time, with every message stamped with the sender's clock
+// (single speed clocks since power on):
+//  - a side waiting for the other to clock it (SC = $80) announces the byte in its
+//    SB with `Armed`, and takes it back with `Disarmed`,
+//  - the side with the internal clock shifts out its byte against the last byte
+//    announced by the other side ($FF if there is none, as with no cable) and sends
+//    it in a `Transfer`,
+//  - the other side shifts the byte in once its own clock reaches the timestamp, so
+//    both see the transfer complete at the same emulated time if it is behind, and
+//    as soon as possible if the message arrived late,
+//  - `Sync` carries just the clock, every `SYNC_INTERVAL` clocks.
+// To keep bytes from being missed, a side must not run more than `max_lead` clocks
+// ahead of the last clock it heard from the other one (`must_wait`). A side starting
+// a transfer with its own clock also waits until it has heard from the other side as
+// of the start, so a byte armed before then isn't missed by a message in flight.
+
+use crate::serial::{Serial, SerialDevice};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+pub const MESSAGE_SIZE: usize = 10;
+/// Default for how far ahead of the other side a Game Boy may run, about two bytes
+/// at the normal serial clock.
+pub const DEFAULT_MAX_LEAD: u64 = 8192;
+const SYNC_INTERVAL: u64 = 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Message {
+    Sync { clock: u64 },
+    Armed { clock: u64, sb: u8 },
+    Disarmed { clock: u64 },
+    Transfer { clock: u64, sb: u8 },
+}
+
+impl Message {
+    /// Kind, SB (0 if unused), then the clock in little endian.
+    pub fn encode(&self) -> [u8; MESSAGE_SIZE] {
+        let (kind, sb, clock) = match *self {
+            Message::Sync { clock } => (0, 0, clock),
+            Message::Armed { clock, sb } => (1, sb, clock),
+            Message::Disarmed { clock } => (2, 0, clock),
+            Message::Transfer { clock, sb } => (3, sb, clock),
+        };
+
+        let mut bytes = [0; MESSAGE_SIZE];
+        bytes[0] = kind;
+        bytes[1] = sb;
+        bytes[2..].copy_from_slice(&clock.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Message> {
+        if bytes.len() != MESSAGE_SIZE {
+            return None;
+        }
+
+        let mut clock = [0; 8];
+        clock.copy_from_slice(&bytes[2..]);
+        let clock = u64::from_le_bytes(clock);
+        let sb = bytes[1];
+
+        match bytes[0] {
+            0 => Some(Message::Sync { clock }),
+            1 => Some(Message::Armed { clock, sb }),
+            2 => Some(Message::Disarmed { clock }),
+            3 => Some(Message::Transfer { clock, sb }),
+            _ => None,
+        }
+    }
+
+    fn clock(&self) -> u64 {
+        match *self {
+            Message::Sync { clock }
+            | Message::Armed { clock, .. }
+            | Message::Disarmed { clock }
+            | Message::Transfer { clock, .. } => clock,
+        }
+    }
+}
+
+/// Carries messages to the other side. Neither call may block.
+pub trait LinkTransport {
+    fn send(&mut self, message: Message) -> io::Result<()>;
+
+    /// The next message received, if any.
+    fn try_recv(&mut self) -> io::Result<Option<Message>>;
+}
+
+/// Both ends of an in-memory link, for tests and for two Game Boys on different
+/// threads.
+pub struct ChannelTransport {
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+}
+
+impl ChannelTransport {
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (tx0, rx1) = mpsc::channel();
+        let (tx1, rx0) = mpsc::channel();
+
+        (
+            ChannelTransport { tx: tx0, rx: rx0 },
+            ChannelTransport { tx: tx1, rx: rx1 },
+        )
+    }
+}
+
+impl LinkTransport for ChannelTransport {
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "link closed"))
+    }
+
+    fn try_recv(&mut self) -> io::Result<Option<Message>> {
+        match self.rx.try_recv() {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "link closed"))
+            }
+        }
+    }
+}
+
+/// Messages over a TCP connection, back to back in their encoded form. What the
+/// socket won't take yet is kept and sent on the next call.
+pub struct TcpTransport {
+    stream: TcpStream,
+    received: Vec<u8>,
+    unsent: Vec<u8>,
+}
+
+impl TcpTransport {
+    /// Wraps a connected stream, from `TcpStream::connect` or `TcpListener::accept`.
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Self {
+            stream,
+            received: Vec::new(),
+            unsent: Vec::new(),
+        })
+    }
+
+    // Writes as much of `unsent` as the socket takes without blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut sent = 0;
+
+        while sent < self.unsent.len() {
+            match self.stream.write(&self.unsent[sent..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.unsent.drain(..sent);
+        Ok(())
+    }
+}
+
+impl LinkTransport for TcpTransport {
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        self.unsent.extend_from_slice(&message.encode());
+        self.flush()
+    }
+
+    fn try_recv(&mut self) -> io::Result<Option<Message>> {
+        self.flush()?;
+
+        let mut buffer = [0; 256];
+
+        while self.received.len() < MESSAGE_SIZE {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let message = Message::decode(&self.received[..MESSAGE_SIZE]);
+        self.received.drain(..MESSAGE_SIZE);
+
+        message
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad link message"))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // Our clock as of the last sync.
+    clock: u64,
+    // The byte the other side is waiting to exchange.
+    remote_sb: Option<u8>,
+    // While a byte is being clocked out: the other side's byte, the bits sent so far
+    // and how many.
+    exchange: Option<(u8, u8, u8)>,
+    // Bytes clocked out, with the clock they finished at.
+    sent: Vec<(u64, u8)>,
+}
+
+/// The end of a `NetworkLink` that plugs into the serial port.
+pub struct NetworkPort {
+    state: Rc<RefCell<State>>,
+}
+
+impl SerialDevice for NetworkPort {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        let mut state = self.state.borrow_mut();
+
+        let (remote, mut sent, bits) = match state.exchange {
+            Some(exchange) => exchange,
+            None => (state.remote_sb.take().unwrap_or(0xFF), 0, 0),
+        };
+
+        let bit_in = remote & (0x80 >> bits) != 0;
+        sent = sent << 1 | bit as u8;
+
+        if bits == 7 {
+            let clock = state.clock;
+            state.sent.push((clock, sent));
+            state.exchange = None;
+        } else {
+            state.exchange = Some((remote, sent, bits + 1));
+        }
+
+        bit_in
+    }
+}
+
+pub struct NetworkLink<T: LinkTransport> {
+    transport: T,
+    state: Rc<RefCell<State>>,
+    max_lead: u64,
+    remote_clock: u64,
+    last_sync: u64,
+    // What we last told the other side about our SB.
+    announced: Option<u8>,
+    // The clock a transfer with our clock started at, until its first bit.
+    transfer_start: Option<u64>,
+    incoming: VecDeque<(u64, u8)>,
+}
+
+impl<T: LinkTransport> NetworkLink<T> {
+    /// A link over `transport` and the port to plug into the serial port.
+    pub fn new(transport: T) -> (Self, NetworkPort) {
+        let state = Rc::new(RefCell::new(State::default()));
+
+        (
+            Self {
+                transport,
+                state: state.clone(),
+                max_lead: DEFAULT_MAX_LEAD,
+                remote_clock: 0,
+                last_sync: 0,
+                announced: None,
+                transfer_start: None,
+                incoming: VecDeque::new(),
+            },
+            NetworkPort { state },
+        )
+    }
+
+    pub fn set_max_lead(&mut self, clocks: u64) {
+        self.max_lead = clocks;
+    }
+
+    /// Whether we're too far ahead of the other side to keep running.
+    pub fn must_wait(&self, clock: u64) -> bool {
+        clock > self.remote_clock + self.max_lead
+            || self
+                .transfer_start
+                .is_some_and(|start| self.remote_clock < start)
+    }
+
+    /// Exchanges messages with the other side and shifts in any bytes due by
+    /// `clock`. Call after every step.
+    pub fn sync(&mut self, serial: &mut Serial, clock: u64) -> io::Result<()> {
+        let sent = {
+            let mut state = self.state.borrow_mut();
+            state.clock = clock;
+            std::mem::take(&mut state.sent)
+        };
+
+        for (clock, sb) in sent {
+            self.transport.send(Message::Transfer { clock, sb })?;
+        }
+
+        while let Some(message) = self.transport.try_recv()? {
+            self.remote_clock = self.remote_clock.max(message.clock());
+
+            match message {
+                Message::Armed { sb, .. } => self.state.borrow_mut().remote_sb = Some(sb),
+                Message::Disarmed { .. } => self.state.borrow_mut().remote_sb = None,
+                Message::Transfer { clock, sb } => self.incoming.push_back((clock, sb)),
+                Message::Sync { .. } => (),
+            }
+        }
+
+        while let Some(&(due, sb)) = self.incoming.front() {
+            if due > clock {
+                break;
+            }
+            self.incoming.pop_front();
+
+            if serial.waiting_for_clock() {
+                for i in 0..8 {
+                    serial.external_clock(sb & (0x80 >> i) != 0);
+                }
+                // Whatever is armed next is a new byte, even if it has the same value.
+                self.announced = None;
+            }
+        }
+
+        let armed = if serial.waiting_for_clock() {
+            Some(serial.get_byte(0xFF01))
+        } else {
+            None
+        };
+
+        // Tell the other side right away how far we got, it may be waiting for us in
+        // turn if it started a transfer too.
+        let pending = serial.clock_bit().is_some() && self.state.borrow().exchange.is_none();
+        if !pending {
+            self.transfer_start = None;
+        } else if self.transfer_start.is_none() {
+            self.transfer_start = Some(clock);
+            self.transport.send(Message::Sync { clock })?;
+            self.last_sync = clock;
+        }
+
+        if armed != self.announced {
+            self.transport.send(match armed {
+                Some(sb) => Message::Armed { clock, sb },
+                None => Message::Disarmed { clock },
+            })?;
+            self.announced = armed;
+            self.last_sync = clock;
+        }
+
+        if clock >= self.last_sync + SYNC_INTERVAL {
+            self.transport.send(Message::Sync { clock })?;
+            self.last_sync = clock;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_message_encoding() {
+        let messages = [
+            Message::Sync { clock: 0 },
+            Message::Armed {
+                clock: 70224,
+                sb: 0x42,
+            },
+            Message::Disarmed { clock: u64::MAX },
+            Message::Transfer {
+                clock: 1 << 40,
+                sb: 0xFF,
+            },
+        ];
+
+        for message in messages.iter() {
+            assert_eq!(Message::decode(&message.encode()), Some(*message));
+        }
+        assert_eq!(Message::decode(&[9; MESSAGE_SIZE]), None);
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut transport = TcpTransport::new(TcpStream::connect(addr).unwrap()).unwrap();
+            transport
+                .send(Message::Transfer { clock: 7, sb: 0x99 })
+                .unwrap();
+
+            loop {
+                if let Some(message) = transport.try_recv().unwrap() {
+                    return message;
+                }
+                thread::yield_now();
+            }
+        });
+
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+        let received = loop {
+            if let Some(message) = server.try_recv().unwrap() {
+                break message;
+            }
+            thread::yield_now();
+        };
+        server.send(Message::Sync { clock: 8 }).unwrap();
+
+        assert_eq!(received, Message::Transfer { clock: 7, sb: 0x99 });
+        assert_eq!(client.join().unwrap(), Message::Sync { clock: 8 });
+    }
+
+    #[test]
+    fn test_tcp_send_with_full_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client =
+            TcpTransport::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap()).unwrap();
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+
+        // More than the socket buffers hold, with nothing reading yet.
+        const COUNT: u64 = 200_000;
+        for clock in 0..COUNT {
+            client.send(Message::Sync { clock }).unwrap();
+        }
+
+        let mut next = 0;
+        while next < COUNT {
+            assert_eq!(client.try_recv().unwrap(), None);
+
+            while let Some(message) = server.try_recv().unwrap() {
+                assert_eq!(message, Message::Sync { clock: next });
+                next += 1;
+            }
+        }
+        assert!(client.unsent.is_empty());
+    }
+}
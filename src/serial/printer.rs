@@ -0,0 +1,425 @@
+// Game Boy Printer. The Game Boy always drives the clock and sends packets:
+//
+//   $88 $33 command compression length(LE16) data... checksum(LE16) $00 $00
+//
+// The checksum is the 16 bit sum of every byte from the command to the end of the
+// data. The printer answers $00 to everything except the last two bytes: $81 (it's
+// alive) and then its status.
+//
+// Commands: $01 INIT clears the buffer, $04 DATA adds 640 bytes (two rows of 20
+// tiles, RLE compressed if compression is 1) and an empty one marks the end of the
+// image, $02 PRINT prints what's in the buffer, $0F STATUS just asks for the status.
+// Printing "takes" a few status requests, games wait for the busy bit to clear
+// before sending more.
+//
+// References: https://gbdev.io/pandocs/Gameboy_Printer.html
+//             https://www.mikrocontroller.net/attachment/34801/gb-printer.txt
+
+use crate::serial::SerialDevice;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const PRINT_WIDTH: usize = 160;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+// The printer has 8KB of RAM, enough for 9 DATA packets.
+const BUFFER_SIZE: usize = 0x2000;
+// How many status requests a print stays busy for.
+const PRINT_POLLS: u8 = 4;
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// One printed image, 160 pixels wide, in RGBA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Print {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /// Blank paper fed before and after the image, in the printer's units (0-15).
+    pub top_margin: u8,
+    pub bottom_margin: u8,
+}
+
+/// Where the prints come out, for the host to collect.
+#[derive(Clone, Default)]
+pub struct PrinterTray {
+    prints: Rc<RefCell<Vec<Print>>>,
+}
+
+impl PrinterTray {
+    /// Drains the images printed so far.
+    pub fn take(&self) -> Vec<Print> {
+        std::mem::take(&mut *self.prints.borrow_mut())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    tray: PrinterTray,
+    state: State,
+    // Bits of the byte coming in and going out.
+    byte_in: u8,
+    byte_out: u8,
+    bits: u8,
+    // The packet being received.
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    sum: u16,
+    // Image data waiting to be printed.
+    buffer: Vec<u8>,
+    status: u8,
+    busy: u8,
+}
+
+impl Printer {
+    /// A printer and the tray its prints come out in.
+    pub fn new() -> (Printer, PrinterTray) {
+        let tray = PrinterTray::default();
+
+        let printer = Printer {
+            tray: tray.clone(),
+            state: State::Magic1,
+            byte_in: 0,
+            byte_out: 0,
+            bits: 0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            sum: 0,
+            buffer: Vec::new(),
+            status: 0,
+            busy: 0,
+        };
+
+        (printer, tray)
+    }
+
+    // Takes a whole byte and returns the one to send back during the next.
+    fn receive(&mut self, byte: u8) -> u8 {
+        if let State::Command
+        | State::Compression
+        | State::LengthLow
+        | State::LengthHigh
+        | State::Data = self.state
+        {
+            self.sum = self.sum.wrapping_add(byte as u16);
+        }
+
+        match self.state {
+            State::Magic1 => {
+                if byte == 0x88 {
+                    self.state = State::Magic2;
+                }
+            }
+            State::Magic2 => {
+                self.state = match byte {
+                    0x33 => State::Command,
+                    0x88 => State::Magic2,
+                    _ => State::Magic1,
+                };
+                self.sum = 0;
+            }
+            State::Command => {
+                self.command = byte;
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.state = State::LengthLow;
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                };
+            }
+            State::Data => {
+                self.data.push(byte);
+                if self.data.len() == self.length as usize {
+                    self.state = State::ChecksumLow;
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum = byte as u16;
+                self.state = State::ChecksumHigh;
+            }
+            State::ChecksumHigh => {
+                self.checksum |= (byte as u16) << 8;
+                self.state = State::Alive;
+
+                if self.checksum == self.sum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                return 0x81;
+            }
+            State::Alive => {
+                self.state = State::Status;
+                return self.status;
+            }
+            State::Status => {
+                self.state = State::Magic1;
+            }
+        }
+
+        0x00
+    }
+
+    fn execute(&mut self) {
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy = 0;
+            }
+            DATA if self.data.is_empty() => self.status |= STATUS_IMAGE_FULL,
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                self.status |= STATUS_UNPROCESSED;
+            }
+            PRINT if self.data.len() == 4 => {
+                let sheets = self.data[0];
+                let margins = self.data[1];
+                let palette = self.data[2];
+
+                if sheets > 0 && !self.buffer.is_empty() {
+                    let print = decode(&self.buffer, palette, margins);
+                    self.tray.prints.borrow_mut().push(print);
+                }
+                self.buffer.clear();
+                self.status = STATUS_PRINTING;
+                self.busy = PRINT_POLLS;
+            }
+            STATUS if self.busy > 0 => {
+                self.busy -= 1;
+                if self.busy == 0 {
+                    self.status &= !STATUS_PRINTING;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange_bit(&mut self, bit: bool) -> bool {
+        let bit_out = self.byte_out & 0x80 != 0;
+        self.byte_out <<= 1;
+        self.byte_in = self.byte_in << 1 | bit as u8;
+        self.bits += 1;
+
+        if self.bits == 8 {
+            self.bits = 0;
+            self.byte_out = self.receive(self.byte_in);
+        }
+
+        bit_out
+    }
+}
+
+// A control byte with bit 7 set repeats the next byte (control & $7F) + 2 times,
+// otherwise (control + 1) bytes follow as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(640);
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(&byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(byte, count));
+            }
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+// Tiles in rows of 20, 2 bits per pixel like VRAM.
+fn decode(buffer: &[u8], palette: u8, margins: u8) -> Print {
+    // Some games send a zero palette and mean the usual one.
+    let palette = if palette == 0 { 0xE4 } else { palette };
+    let tiles = buffer.len() / 16;
+    let height = tiles.div_ceil(20) * 8;
+    let mut pixels = vec![0xFF; PRINT_WIDTH * height * 4];
+
+    for tile in 0..tiles {
+        let x0 = tile % 20 * 8;
+        let y0 = tile / 20 * 8;
+
+        for row in 0..8 {
+            let lo = buffer[tile * 16 + row * 2];
+            let hi = buffer[tile * 16 + row * 2 + 1];
+
+            for col in 0..8 {
+                let colour = (hi >> (7 - col) & 1) << 1 | lo >> (7 - col) & 1;
+                let shade = SHADES[(palette >> (colour * 2) & 0x03) as usize];
+                let i = ((y0 + row) * PRINT_WIDTH + x0 + col) * 4;
+                pixels[i..i + 3].copy_from_slice(&[shade; 3]);
+            }
+        }
+    }
+
+    Print {
+        width: PRINT_WIDTH,
+        height,
+        pixels,
+        top_margin: margins >> 4,
+        bottom_margin: margins & 0x0F,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_byte(printer: &mut Printer, byte: u8) -> u8 {
+        let mut received = 0;
+        for i in 0..8 {
+            let bit = printer.exchange_bit(byte & (0x80 >> i) != 0);
+            received = received << 1 | bit as u8;
+        }
+        received
+    }
+
+    // Sends a packet, returning the last two bytes received (alive and status).
+    fn send_packet(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+        let len = data.len() as u16;
+        let mut body = vec![command, compression, len as u8, (len >> 8) as u8];
+        body.extend_from_slice(data);
+        let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+
+        send_byte(printer, 0x88);
+        send_byte(printer, 0x33);
+        for &b in body.iter() {
+            assert_eq!(send_byte(printer, b), 0x00);
+        }
+        send_byte(printer, checksum as u8);
+        send_byte(printer, (checksum >> 8) as u8);
+
+        (send_byte(printer, 0x00), send_byte(printer, 0x00))
+    }
+
+    #[test]
+    fn test_print() {
+        let (mut printer, tray) = Printer::new();
+        assert_eq!(send_packet(&mut printer, INIT, 0, &[]), (0x81, 0x00));
+
+        // Two rows of tiles: every tile has colour 1 in the top row of pixels and
+        // colour 3 in the others. Compressed: a row of ($FF $00) then 7 rows of
+        // ($FF $FF), 40 times.
+        let mut tile = vec![0x01, 0xFF, 0x00];
+        tile.push(0x80 + 14 - 2);
+        tile.push(0xFF);
+        let compressed: Vec<u8> = tile.iter().cloned().cycle().take(40 * 5).collect();
+
+        let (_, status) = send_packet(&mut printer, DATA, 1, &compressed);
+        assert_eq!(status, STATUS_UNPROCESSED);
+        let (_, status) = send_packet(&mut printer, DATA, 0, &[]);
+        assert_eq!(status, STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+
+        // 1 sheet, margins 1 and 3, colour 1 black, colour 3 white.
+        let (_, status) = send_packet(&mut printer, PRINT, 0, &[1, 0x13, 0x0C, 0x40]);
+        assert_eq!(status, STATUS_PRINTING);
+
+        for _ in 1..PRINT_POLLS {
+            assert_eq!(send_packet(&mut printer, STATUS, 0, &[]).1, STATUS_PRINTING);
+        }
+        assert_eq!(send_packet(&mut printer, STATUS, 0, &[]).1, 0x00);
+
+        let prints = tray.take();
+        assert_eq!(prints.len(), 1);
+
+        let print = &prints[0];
+        assert_eq!((print.width, print.height), (160, 16));
+        assert_eq!((print.top_margin, print.bottom_margin), (1, 3));
+        assert_eq!(&print.pixels[0..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&print.pixels[160 * 4..160 * 4 + 4], &[0xFF; 4]);
+        assert_eq!(
+            &print.pixels[(160 * 8 + 159) * 4..][..4],
+            &[0x00, 0x00, 0x00, 0xFF]
+        );
+        assert!(tray.take().is_empty());
+    }
+
+    #[test]
+    fn test_checksum_error() {
+        let (mut printer, tray) = Printer::new();
+        send_packet(&mut printer, DATA, 0, &[0xFF; 640]);
+
+        // A PRINT packet with a bad checksum is ignored.
+        for &b in [0x88, 0x33, PRINT, 0, 4, 0, 1, 0, 0xE4, 0x40, 0x00, 0x00].iter() {
+            send_byte(&mut printer, b);
+        }
+        assert_eq!(send_byte(&mut printer, 0x00), 0x81);
+        assert_eq!(
+            send_byte(&mut printer, 0x00),
+            STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR
+        );
+        assert!(tray.take().is_empty());
+
+        // The next good packet clears the error.
+        assert_eq!(
+            send_packet(&mut printer, STATUS, 0, &[]).1,
+            STATUS_UNPROCESSED
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!(
+            decompress(&[0x02, 1, 2, 3, 0x81, 9, 0x00, 4]),
+            vec![1, 2, 3, 9, 9, 9, 4]
+        );
+    }
+}
@@ -3,6 +3,8 @@ use crate::cpu::EmulationMode;
 const COUNTER_SHIFT: [u16; 4] = [9, 3, 5, 7];
 const TRIGGER_CLOCKS: [u16; 4] = [512, 8, 32, 128];
 
+// The 16-bit system counter, DIV is its upper byte. The timer, the APU frame
+// sequencer and the serial clock all count falling edges of its bits.
 pub struct Divider {
     pub counter: u16,
 }
@@ -30,6 +32,18 @@ impl Divider {
     }
 }
 
+/// Clocks until `bit` of the system counter next goes from 1 to 0.
+pub fn clocks_until_fall(counter: u16, bit: u16) -> usize {
+    let period = 1usize << (bit + 1);
+    period - (counter as usize & (period - 1))
+}
+
+/// The number of times `bit` goes from 1 to 0 while the system counter advances
+/// `cycles` clocks from `counter`.
+pub fn falls(counter: u16, cycles: usize, bit: u16) -> usize {
+    ((counter as usize + cycles) >> (bit + 1)) - (counter as usize >> (bit + 1))
+}
+
 #[derive(Debug, PartialEq)]
 enum TimerState {
     Reloading,
@@ -66,8 +80,22 @@ impl Timer {
         }
     }
 
-    pub fn tick(&mut self, cycles: usize) {
-        for _ in 0..cycles {
+    pub fn tick(&mut self, mut cycles: usize) {
+        while cycles > 0 {
+            // Nothing happens between falling edges while running, skip straight to
+            // the clock before the next one.
+            if self.state == TimerState::Running {
+                let skip = cycles.min(self.clocks_until_edge() - 1);
+                self.divider.tick(skip);
+                self.clock = (self.clock + skip) % 4;
+                cycles -= skip;
+
+                if cycles == 0 {
+                    break;
+                }
+            }
+
+            cycles -= 1;
             self.clock += 1;
             let old_signal = self.signal();
             self.divider.tick(1);
@@ -80,6 +108,24 @@ impl Timer {
         }
     }
 
+    /// Clocks until the timer next does something visible outside its registers:
+    /// an increment of TIMA, or every clock while a reload is in progress.
+    pub fn cycles_until_event(&self) -> usize {
+        match self.state {
+            TimerState::Running => self.clocks_until_edge(),
+            _ => 1,
+        }
+    }
+
+    // Clocks until the selected divider bit next falls, TIMA counts on that edge.
+    fn clocks_until_edge(&self) -> usize {
+        if self.timer_enable == 0 {
+            return usize::MAX;
+        }
+
+        clocks_until_fall(self.divider.counter, self.tima_bit)
+    }
+
     fn advance_state(&mut self) {
         match self.state {
             TimerState::Reloading => {
@@ -115,10 +161,12 @@ impl Timer {
         let old_period = TRIGGER_CLOCKS[self.freq as usize];
         let new_period = TRIGGER_CLOCKS[(value & 0x3) as usize];
 
-        if self.divider.counter & old_period != 0 {
-            if value & 4 == 0 || self.divider.counter & new_period != 0 {
-                self.increment_tima();
-            }
+        // The selected bit goes through an AND with the enable bit before the
+        // falling edge detector, so switching from a 1 to a 0 counts as an edge.
+        if self.divider.counter & old_period != 0
+            && (value & 4 == 0 || self.divider.counter & new_period == 0)
+        {
+            self.increment_tima();
         }
     }
 
@@ -250,4 +298,82 @@ mod tests {
         let e = a;
         println!("E: {}", e);
     }
+
+    #[test]
+    fn test_bulk_tick_matches_per_clock() {
+        for &tac in &[0x04, 0x05, 0x06, 0x07, 0x00] {
+            let mut per_clock = Timer::new(EmulationMode::Dmg);
+            let mut bulk = Timer::new(EmulationMode::Dmg);
+
+            for timer in [&mut per_clock, &mut bulk].iter_mut() {
+                timer.set_byte(TMA, 0xF0);
+                timer.set_byte(TAC, tac);
+            }
+
+            for i in 0..5000 {
+                let cycles = i % 7 * 4;
+                for _ in 0..cycles {
+                    per_clock.tick(1);
+                }
+                bulk.tick(cycles);
+
+                assert_eq!(bulk.get_byte(DIV), per_clock.get_byte(DIV));
+                assert_eq!(bulk.get_byte(TIMA), per_clock.get_byte(TIMA));
+                assert_eq!(bulk.request_timer_int, per_clock.request_timer_int);
+                per_clock.request_timer_int = false;
+                bulk.request_timer_int = false;
+            }
+        }
+    }
+
+    #[test]
+    fn test_div_write_falling_edge() {
+        for (freq, &bit) in COUNTER_SHIFT.iter().enumerate() {
+            for &set in &[true, false] {
+                let mut timer = Timer::new(EmulationMode::Dmg);
+                timer.set_byte(TAC, 0x04 | freq as u8);
+                timer.set_byte(DIV, 0);
+                timer.set_byte(TIMA, 0x10);
+
+                // Run until the selected bit is set (or just cleared again).
+                timer.tick(if set { 1 << bit } else { 2 << bit });
+                let tima = timer.get_byte(TIMA);
+                timer.set_byte(DIV, 0);
+
+                let expected = if set { tima + 1 } else { tima };
+                assert_eq!(timer.get_byte(TIMA), expected, "TAC {}", freq);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tac_write_falling_edge() {
+        let mut timer = Timer::new(EmulationMode::Dmg);
+        timer.set_byte(TAC, 0x05);
+        timer.set_byte(DIV, 0);
+        timer.set_byte(TIMA, 0);
+
+        // Bit 3 set, bit 7 clear: moving to 16 KHz is a falling edge.
+        timer.tick(8);
+        timer.set_byte(TAC, 0x07);
+        assert_eq!(timer.get_byte(TIMA), 1);
+
+        // Bit 7 clear, bit 3 set: moving back isn't.
+        timer.set_byte(TAC, 0x05);
+        assert_eq!(timer.get_byte(TIMA), 1);
+
+        // Disabling with the selected bit set is.
+        timer.set_byte(TAC, 0x01);
+        assert_eq!(timer.get_byte(TIMA), 2);
+    }
+
+    #[test]
+    fn test_falls() {
+        assert_eq!(clocks_until_fall(0x0000, 12), 0x2000);
+        assert_eq!(clocks_until_fall(0x1FFF, 12), 1);
+        assert_eq!(falls(0x1FFC, 4, 12), 1);
+        assert_eq!(falls(0x1FFB, 4, 12), 0);
+        assert_eq!(falls(0xFFFE, 4, 12), 1);
+        assert_eq!(falls(0x0000, 64, 3), 4);
+    }
 }
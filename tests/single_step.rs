@@ -0,0 +1,280 @@
+// Per-opcode cpu verification against the SM83 SingleStepTests vectors
+// (https://github.com/SingleStepTests/sm83). Point GBEMU_SINGLE_STEP_TESTS at the
+// directory holding the `v1/*.json` files and run
+// `cargo test --test single_step -- --nocapture`. Without the variable the test is
+// skipped.
+//
+// Each vector gives the registers and RAM before and after one instruction, plus the
+// bus activity of every M-cycle. The instruction is run on a cpu wired to a flat 64K
+// bus, wrapped to log one entry per M-cycle: the read or write made during that
+// cycle, or nothing for internal delays.
+
+use gbemu::cpu::{Bus, Cpu, EmulationMode, FlatBus, R16, R8};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+const TESTS_DIR_VAR: &str = "GBEMU_SINGLE_STEP_TESTS";
+// Failures printed per opcode file, the rest are only counted.
+const MAX_REPORTED: usize = 3;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Cycle {
+    Idle,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+struct CycleLog {
+    bus: FlatBus,
+    access: Option<Cycle>,
+    cycles: Vec<Cycle>,
+}
+
+impl CycleLog {
+    fn new() -> Self {
+        Self {
+            bus: FlatBus::new(),
+            access: None,
+            cycles: Vec::new(),
+        }
+    }
+}
+
+impl Bus for CycleLog {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.bus.read(addr);
+        self.access = Some(Cycle::Read(addr, value));
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bus.write(addr, value);
+        self.access = Some(Cycle::Write(addr, value));
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles / 4 {
+            let cycle = self.access.take().unwrap_or(Cycle::Idle);
+            self.cycles.push(cycle);
+        }
+    }
+
+    // Looked up without going through `read` so it doesn't show up as bus activity.
+    fn pending_interrupts(&mut self) -> u8 {
+        self.bus.pending_interrupts()
+    }
+}
+
+#[test]
+fn test_single_step() {
+    let dir = match env::var_os(TESTS_DIR_VAR) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            println!("{} not set, skipping SingleStepTests", TESTS_DIR_VAR);
+            return;
+        }
+    };
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    let mut total = 0;
+    let mut failures = 0;
+
+    for file in &files {
+        let text = fs::read_to_string(file).unwrap();
+        let vectors: Vec<Value> = serde_json::from_str(&text)
+            .unwrap_or_else(|e| panic!("Could not parse {}: {}", file.display(), e));
+
+        let mut file_failures = 0;
+
+        for vector in &vectors {
+            if let Err(msg) = run_vector(vector) {
+                if file_failures < MAX_REPORTED {
+                    println!("{}: {}", vector["name"].as_str().unwrap_or("?"), msg);
+                }
+                file_failures += 1;
+            }
+        }
+
+        if file_failures > 0 {
+            println!(
+                "{}: {} of {} failed",
+                file.file_name().unwrap().to_string_lossy(),
+                file_failures,
+                vectors.len()
+            );
+        }
+
+        total += vectors.len();
+        failures += file_failures;
+    }
+
+    println!("{} of {} vectors passed", total - failures, total);
+
+    assert_eq!(failures, 0);
+}
+
+fn run_vector(vector: &Value) -> Result<(), String> {
+    let initial = &vector["initial"];
+    let expected = &vector["final"];
+
+    let mut cpu = Cpu::with_bus(CycleLog::new(), EmulationMode::Dmg);
+    load_state(&mut cpu, initial);
+
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let opcode = cpu.fetch();
+        cpu.decode_exec(opcode);
+    }))
+    .map_err(|_| String::from("panicked"))?;
+
+    check_state(&cpu, expected)?;
+    check_cycles(&cpu.mmu.cycles, &vector["cycles"])
+}
+
+fn load_state(cpu: &mut Cpu<CycleLog>, state: &Value) {
+    for (name, r) in registers() {
+        cpu.set_r8(r, number(state, name) as u8);
+    }
+
+    cpu.pc = number(state, "pc") as u16;
+    cpu.set_r16(R16::SP, number(state, "sp") as u16);
+    cpu.set_ime(number(state, "ime") != 0);
+
+    for (addr, value) in ram(state) {
+        cpu.mmu.bus.ram[addr as usize] = value;
+    }
+
+    if let Some(ie) = state["ie"].as_u64() {
+        cpu.mmu.bus.ram[0xFFFF] = ie as u8;
+    }
+
+    cpu.mmu.access = None;
+    cpu.mmu.cycles.clear();
+}
+
+fn check_state(cpu: &Cpu<CycleLog>, state: &Value) -> Result<(), String> {
+    for (name, r) in registers() {
+        let actual = cpu.get_r8(&r) as u64;
+        let expected = number(state, name);
+        if actual != expected {
+            return Err(format!(
+                "{} = {:02X}, expected {:02X}",
+                name, actual, expected
+            ));
+        }
+    }
+
+    if cpu.pc as u64 != number(state, "pc") {
+        return Err(format!(
+            "pc = {:04X}, expected {:04X}",
+            cpu.pc,
+            number(state, "pc")
+        ));
+    }
+
+    let sp = cpu.get_r16(&R16::SP) as u64;
+    if sp != number(state, "sp") {
+        return Err(format!(
+            "sp = {:04X}, expected {:04X}",
+            sp,
+            number(state, "sp")
+        ));
+    }
+
+    if state["ime"].is_u64() && cpu.ime() != (number(state, "ime") != 0) {
+        return Err(format!("ime = {}, expected {}", cpu.ime(), !cpu.ime()));
+    }
+
+    for (addr, value) in ram(state) {
+        let actual = cpu.mmu.bus.ram[addr as usize];
+        if actual != value {
+            return Err(format!(
+                "({:04X}) = {:02X}, expected {:02X}",
+                addr, actual, value
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_cycles(actual: &[Cycle], expected: &Value) -> Result<(), String> {
+    let expected: Vec<Cycle> = expected
+        .as_array()
+        .map(|cycles| cycles.iter().map(parse_cycle).collect())
+        .unwrap_or_default();
+
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "took {} M-cycles, expected {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+
+    for (i, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+        if actual != expected {
+            return Err(format!(
+                "M-cycle {}: {:?}, expected {:?}",
+                i, actual, expected
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Cycles are `[addr, value, "r-m"]` style triples, or null. Idle cycles still carry
+// whatever was on the address bus, which we don't model, so only the kind is kept.
+fn parse_cycle(cycle: &Value) -> Cycle {
+    let addr = cycle[0].as_u64().unwrap_or(0) as u16;
+    let value = cycle[1].as_u64().unwrap_or(0) as u8;
+
+    match cycle[2].as_str() {
+        Some(kind) if kind.starts_with('r') => Cycle::Read(addr, value),
+        Some(kind) if kind.contains('w') => Cycle::Write(addr, value),
+        _ => Cycle::Idle,
+    }
+}
+
+fn registers() -> Vec<(&'static str, R8)> {
+    vec![
+        ("a", R8::A),
+        ("f", R8::F),
+        ("b", R8::B),
+        ("c", R8::C),
+        ("d", R8::D),
+        ("e", R8::E),
+        ("h", R8::H),
+        ("l", R8::L),
+    ]
+}
+
+fn number(state: &Value, name: &str) -> u64 {
+    state[name].as_u64().unwrap_or(0)
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry[0].as_u64().unwrap_or(0) as u16,
+                        entry[1].as_u64().unwrap_or(0) as u8,
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
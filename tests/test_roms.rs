@@ -0,0 +1,290 @@
+// Conformance harness for the common community test ROMs. Point GBEMU_TEST_ROMS at a
+// directory containing the ROMs (any layout, searched recursively) and run
+// `cargo test --test test_roms -- --nocapture` to get a pass/fail/timeout report.
+// Without the variable the test is skipped.
+//
+// Results are detected the way each suite reports them:
+//  - blargg: text written to the serial port, or the text at $A004 once $A001-$A003
+//    hold the signature DE B0 61 and the status at $A000 is no longer $80.
+//    https://github.com/retrio/gb-test-roms
+//  - mooneye: `LD B,B` followed by the Fibonacci numbers 3/5/8/13/21/34 in B-L on
+//    success, or $42 in all of them on failure.
+//    https://github.com/Gekkio/mooneye-test-suite
+//  - dmg-acid2 / cgb-acid2: the screen after `LD B,B` compared against a reference
+//    PNG with the same name next to the ROM (e.g. dmg-acid2.gb + dmg-acid2.png).
+//    https://github.com/mattcurrie/dmg-acid2
+
+use gbemu::cpu::R8;
+use gbemu::gameboy::{DmgPreset, GameBoy, FRAME_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+const ROM_DIR_VAR: &str = "GBEMU_TEST_ROMS";
+
+// Emulated seconds before a ROM is given up on. cpu_instrs is the slowest of the
+// lot at just under a minute.
+const TIMEOUT_FRAMES: usize = 60 * 90;
+// Frames to let the acid2 ROMs finish drawing after their `LD B,B`.
+const ACID2_SETTLE_FRAMES: usize = 2;
+
+const LD_B_B: u8 = 0x40;
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILURE: u8 = 0x42;
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::Fail(reason) => write!(f, "fail ({})", reason),
+            Outcome::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+#[test]
+fn test_roms() {
+    let dir = match env::var_os(ROM_DIR_VAR) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            println!("{} not set, skipping test ROMs", ROM_DIR_VAR);
+            return;
+        }
+    };
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+
+    let mut failures = 0;
+
+    for rom in &roms {
+        let outcome = run_rom(rom);
+        if outcome != Outcome::Pass {
+            failures += 1;
+        }
+
+        println!(
+            "{:<8} {}",
+            outcome.to_string(),
+            rom.strip_prefix(&dir).unwrap_or(rom).display()
+        );
+    }
+
+    println!(
+        "{} of {} test ROMs passed",
+        roms.len() - failures,
+        roms.len()
+    );
+
+    assert_eq!(failures, 0);
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|e| panic!("Could not read {}: {}", dir.display(), e));
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if let Some("gb") | Some("gbc") = path.extension().and_then(|ext| ext.to_str()) {
+            roms.push(path);
+        }
+    }
+}
+
+fn run_rom(path: &Path) -> Outcome {
+    let rom = fs::read(path).unwrap();
+    let mut gb = GameBoy::new(rom);
+    // The same greys as the dmg-acid2 reference image.
+    gb.set_dmg_palette(DmgPreset::Greyscale.into());
+
+    let is_acid2 = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains("acid2"));
+
+    let mut serial = Vec::new();
+
+    for _ in 0..TIMEOUT_FRAMES {
+        let mut cycles = 0;
+
+        while cycles < FRAME_CYCLES {
+            let pc = gb.cpu().pc;
+            let opcode = gb.cpu_mut().mmu.get_byte(pc);
+
+            cycles += gb.step();
+
+            if opcode != LD_B_B {
+                continue;
+            }
+
+            if is_acid2 {
+                return compare_screen(&mut gb, path);
+            }
+
+            if let Some(outcome) = mooneye_result(&gb) {
+                return outcome;
+            }
+        }
+
+        // Audio isn't checked, drop it so it doesn't pile up.
+        gb.audio_samples();
+
+        serial.extend(gb.serial_output());
+        if let Some(outcome) = serial_result(&serial) {
+            return outcome;
+        }
+
+        if let Some(outcome) = memory_result(&mut gb) {
+            return outcome;
+        }
+    }
+
+    Outcome::Timeout
+}
+
+fn mooneye_result(gb: &GameBoy) -> Option<Outcome> {
+    let cpu = gb.cpu();
+    let registers = [
+        cpu.get_r8(&R8::B),
+        cpu.get_r8(&R8::C),
+        cpu.get_r8(&R8::D),
+        cpu.get_r8(&R8::E),
+        cpu.get_r8(&R8::H),
+        cpu.get_r8(&R8::L),
+    ];
+
+    if registers == FIBONACCI {
+        Some(Outcome::Pass)
+    } else if registers.iter().all(|&r| r == MOONEYE_FAILURE) {
+        Some(Outcome::Fail(String::from("registers = $42")))
+    } else {
+        // Not a mooneye result, some other ROM just happens to use `LD B,B`.
+        None
+    }
+}
+
+fn serial_result(serial: &[u8]) -> Option<Outcome> {
+    let text = String::from_utf8_lossy(serial);
+
+    if text.contains("Passed") {
+        Some(Outcome::Pass)
+    } else if text.contains("Failed") {
+        Some(Outcome::Fail(last_line(&text)))
+    } else {
+        None
+    }
+}
+
+fn memory_result(gb: &mut GameBoy) -> Option<Outcome> {
+    let mmu = &mut gb.cpu_mut().mmu;
+
+    let signature = [
+        mmu.get_byte(0xA001),
+        mmu.get_byte(0xA002),
+        mmu.get_byte(0xA003),
+    ];
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    let status = mmu.get_byte(0xA000);
+    if status == BLARGG_RUNNING {
+        return None;
+    }
+
+    if status == 0 {
+        return Some(Outcome::Pass);
+    }
+
+    let mut text = Vec::new();
+    let mut addr = 0xA004;
+    while addr < 0xC000 {
+        match mmu.get_byte(addr) {
+            0 => break,
+            byte => text.push(byte),
+        }
+        addr += 1;
+    }
+
+    Some(Outcome::Fail(format!(
+        "status {}: {}",
+        status,
+        last_line(&String::from_utf8_lossy(&text))
+    )))
+}
+
+fn last_line(text: &str) -> String {
+    text.lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("")
+        .to_string()
+}
+
+fn compare_screen(gb: &mut GameBoy, rom: &Path) -> Outcome {
+    for _ in 0..ACID2_SETTLE_FRAMES {
+        gb.step_frame();
+    }
+
+    let reference_path = rom.with_extension("png");
+    let reference = match load_png(&reference_path) {
+        Some(reference) => reference,
+        None => return Outcome::Fail(format!("missing reference {}", reference_path.display())),
+    };
+
+    let screen = gb.framebuffer();
+
+    let mismatched = screen
+        .chunks(4)
+        .zip(reference.chunks(4))
+        .filter(|(actual, expected)| actual[..3] != expected[..3])
+        .count();
+
+    if mismatched == 0 {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("{} pixels differ from reference", mismatched))
+    }
+}
+
+fn load_png(path: &Path) -> Option<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path).ok()?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().ok()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).ok()?;
+
+    if info.width as usize != SCREEN_WIDTH || info.height as usize != SCREEN_HEIGHT {
+        return None;
+    }
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data,
+        png::ColorType::Rgb => data
+            .chunks(3)
+            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&v| vec![v, v, v, 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks(2)
+            .flat_map(|va| vec![va[0], va[0], va[0], va[1]])
+            .collect(),
+        _ => return None,
+    };
+
+    Some(rgba)
+}